@@ -3,24 +3,65 @@ use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
 
 #[derive(Default, PartialEq, serde::Deserialize)]
 pub struct CallbackQueryProps {
-    pub uid: Option<String>, // this uid will need to be sent to the client as a token
-                             // the uid will also be sent to the client as a token on GUPPY_ROOT, meaning we'll have signed in here and there!
+    pub code: Option<String>,
+    pub state: Option<String>,
+}
+
+#[get("/api/auth/login/{provider}")]
+pub async fn login_request(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
+    let provider = req.match_info().get("provider").unwrap().to_string();
+
+    match data.db.oauth.begin_login(provider).await {
+        Ok(url) => HttpResponse::Found()
+            .append_header(("Location", url))
+            .finish(),
+        Err(e) => HttpResponse::NotFound().body(e.to_string()),
+    }
 }
 
 #[get("/api/auth/callback")]
-pub async fn callback_request(info: web::Query<CallbackQueryProps>) -> impl Responder {
-    let set_cookie = if info.uid.is_some() {
-        format!("__Secure-Token={}; SameSite=Lax; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age={}", info.uid.as_ref().unwrap(), 60 * 60 * 24 * 365)
-    } else {
-        String::new()
+pub async fn callback_request(
+    info: web::Query<CallbackQueryProps>,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    if info.code.is_none() | info.state.is_none() {
+        return HttpResponse::NotAcceptable().body("Missing code or state");
+    }
+
+    // finish login
+    let login = match data
+        .db
+        .oauth
+        .finish_login(
+            info.code.as_ref().unwrap().to_string(),
+            info.state.as_ref().unwrap().to_string(),
+        )
+        .await
+    {
+        Ok(l) => l,
+        Err(e) => return HttpResponse::NotAcceptable().body(e.to_string()),
     };
 
+    let user = match login.user {
+        Some(u) => u,
+        // first login, create a user for the identity
+        None => match data.db.create_linked_user(&login.identity).await {
+            Ok(u) => u,
+            Err(e) => return HttpResponse::NotAcceptable().body(e.to_string()),
+        },
+    };
+
+    // a new session token is sent to the client (other sessions stay valid)
+    let token = match data.db.auth.issue_token(&user) {
+        Ok(t) => t,
+        Err(e) => return HttpResponse::NotAcceptable().body(e.to_string()),
+    };
+
+    let set_cookie = data.db.token_cookie(&token);
+
     // return
     return HttpResponse::Ok()
-        .append_header((
-            "Set-Cookie",
-            if info.uid.is_some() { &set_cookie } else { "" },
-        ))
+        .append_header(("Set-Cookie", set_cookie))
         .append_header(("Content-Type", "text/html"))
         .body(
             "<head>
@@ -37,13 +78,9 @@ pub async fn logout(req: HttpRequest, data: web::Data<AppData>) -> impl Responde
         return HttpResponse::NotAcceptable().body("Missing token");
     }
 
-    let res = data
-        .db
-        .auth
-        .get_user_by_unhashed(cookie.unwrap().value().to_string()) // if the user is returned, that means the ID is valid
-        .await;
+    let res = data.db.verify_token(cookie.unwrap().value().to_string()); // if the claims are returned, that means the token is valid
 
-    if !res.success {
+    if res.is_err() {
         return HttpResponse::NotAcceptable().body("Invalid token");
     }
 
@@ -53,3 +90,28 @@ pub async fn logout(req: HttpRequest, data: web::Data<AppData>) -> impl Responde
         .append_header(("Content-Type", "text/plain"))
         .body("You have been signed out. You can now close this tab.");
 }
+
+#[get("/api/auth/refresh")]
+pub async fn refresh_request(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
+    let cookie = req.cookie("__Secure-Token");
+
+    if cookie.is_none() {
+        return HttpResponse::NotAcceptable().body("Missing token");
+    }
+
+    // the user is checked again, so bans and revocations apply
+    let (token, set_cookie) = match data
+        .db
+        .refresh_token(cookie.unwrap().value().to_string())
+        .await
+    {
+        Ok(t) => t,
+        Err(e) => return HttpResponse::NotAcceptable().body(e.to_string()),
+    };
+
+    // return
+    return HttpResponse::Ok()
+        .append_header(("Set-Cookie", set_cookie))
+        .append_header(("Content-Type", "text/plain"))
+        .body(token);
+}
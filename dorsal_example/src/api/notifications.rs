@@ -0,0 +1,25 @@
+use crate::db::AppData;
+use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
+
+#[get("/api/notifications/stream")]
+pub async fn stream_request(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
+    let cookie = req.cookie("__Secure-Token");
+
+    if cookie.is_none() {
+        return HttpResponse::NotAcceptable().body("Missing token");
+    }
+
+    let claims = match data.db.verify_token(cookie.unwrap().value().to_string()) {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::NotAcceptable().body("Invalid token"),
+    };
+
+    // return
+    dorsal::sse::notification_events(
+        &data.db.notifications,
+        &req,
+        claims.sub,
+        dorsal::SseOptions::default(),
+    )
+    .await
+}
@@ -1,4 +1,7 @@
+use dorsal::db::special::auth_db::{AuthError, FullUser, UserMetadata};
+use dorsal::db::special::oauth_db::ExternalIdentity;
 use dorsal::query as sqlquery;
+use dorsal::token::{TokenClaims, TokenKey, TokenKeyring};
 
 #[derive(Clone)]
 pub struct AppData {
@@ -13,24 +16,70 @@ pub struct Database {
     pub auth: dorsal::AuthDatabase,
     pub logs: dorsal::LogDatabase,
     pub notifications: dorsal::NotificationDatabase,
+    pub oauth: dorsal::OAuthDatabase,
 }
 
 impl Database {
     pub async fn new(opts: dorsal::DatabaseOpts) -> Database {
         let db = dorsal::StarterDatabase::new(opts).await;
 
-        let auth = dorsal::AuthDatabase { base: db.clone() };
-        let logs = dorsal::LogDatabase { base: db.clone() };
+        let logs = dorsal::LogDatabase::new(db.clone(), Default::default()).await;
+        // sessions are stateless tokens
+        let auth = dorsal::AuthDatabase::new(
+            db.clone(),
+            dorsal::db::special::auth_db::DatabaseOptions {
+                tokens: Some(TokenKeyring {
+                    current: String::from("main"),
+                    keys: vec![TokenKey {
+                        kid: String::from("main"),
+                        // a random secret would sign every user out on restart
+                        secret: match std::env::var("TOKEN_SECRET") {
+                            Ok(s) if !s.is_empty() => s.into_bytes(),
+                            _ => panic!("Missing required TOKEN_SECRET setting!"),
+                        },
+                    }],
+                    lifetime: 60 * 60 * 24,
+                    ..Default::default()
+                }),
+                identities_table: Some(String::from("Identities")),
+                ..Default::default()
+            },
+        )
+        .await
+        .with_logs(logs.clone());
+
+        // guppy is used as an oauth provider
+        let guppy = std::env::var("GUPPY_ROOT").unwrap_or_default();
+        let oauth = dorsal::OAuthDatabase::new(
+            db.clone(),
+            auth.clone(),
+            dorsal::db::special::oauth_db::DatabaseOptions {
+                providers: vec![dorsal::OAuthProvider {
+                    name: String::from("guppy"),
+                    client_id: std::env::var("GUPPY_CLIENT_ID").unwrap_or_default(),
+                    client_secret: std::env::var("GUPPY_CLIENT_SECRET").ok(),
+                    authorize_url: format!("{guppy}/oauth/authorize"),
+                    token_url: format!("{guppy}/oauth/token"),
+                    userinfo_url: format!("{guppy}/oauth/userinfo"),
+                    redirect_url: format!(
+                        "{}/api/auth/callback",
+                        std::env::var("HOST_ROOT").unwrap_or_default()
+                    ),
+                    scopes: vec![String::from("profile")],
+                    subject_field: String::from("sub"),
+                }],
+                ..Default::default()
+            },
+        )
+        .await;
 
         Database {
             base: db.clone(),
             auth: auth.clone(),
             logs: logs.clone(),
-            notifications: dorsal::NotificationDatabase {
-                base: db,
-                auth,
-                logs,
-            },
+            notifications: dorsal::NotificationDatabase::new(db, auth, logs, Default::default())
+                .await,
+            oauth,
         }
     }
 
@@ -45,36 +94,83 @@ impl Database {
         .execute(c)
         .await;
 
-        // users and logs tables
-        let _ = sqlquery(
-            "CREATE TABLE IF NOT EXISTS \"Users\" (
-                username VARCHAR(1000000),
-                id_hashed VARCHAR(1000000),
-                role VARCHAR(1000000),
-                timestamp VARCHAR(1000000),
-                metadata VARCHAR(1000000)
-            )",
-        )
-        .execute(c)
-        .await;
-
-        let _ = sqlquery(
-            "CREATE TABLE IF NOT EXISTS \"Logs\" (
-                id VARCHAR(1000000),
-                logtype VARCHAR(1000000),
-                timestamp  VARCHAR(1000000),
-                content VARCHAR(1000000)
-            )",
-        )
-        .execute(c)
-        .await;
+        // dorsal tables
+        self.auth.init().await;
+        self.logs.init().await;
+        self.notifications.init().await;
+        self.oauth.init().await;
     }
 
     // example
 
     // GET
-    // ...
+    /// Verify a session token (the `__Secure-Token` cookie), returning its claims
+    ///
+    /// This doesn't access the database, so a banned user's token stays valid until it
+    /// expires. Expired tokens are refreshed with [`Database::refresh_token`], which checks
+    /// the user again.
+    pub fn verify_token(&self, token: String) -> Result<TokenClaims, AuthError> {
+        self.auth.verify_token(&token)
+    }
+
+    /// Refresh a session token, returning the new token and its `Set-Cookie` header
+    pub async fn refresh_token(&self, token: String) -> Result<(String, String), AuthError> {
+        let token = self.auth.refresh_token(&token).await?;
+        let cookie = self.token_cookie(&token);
+        Ok((token, cookie))
+    }
+
+    /// Get the `Set-Cookie` header of a session token
+    ///
+    /// The cookie outlives the token by [`TokenKeyring::refresh_window`] so expired
+    /// tokens can still be refreshed.
+    pub fn token_cookie(&self, token: &str) -> String {
+        let keyring = self.auth.options.tokens.as_ref().unwrap();
+
+        format!(
+            "__Secure-Token={}; SameSite=Lax; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age={}",
+            token,
+            keyring.lifetime + keyring.refresh_window
+        )
+    }
 
     // SET
-    // ...
+    /// Create a user for an external identity that isn't linked to a user yet (first login)
+    ///
+    /// The user is named after the identity's subject if it's a valid username that isn't
+    /// taken, and gets a random username otherwise.
+    pub async fn create_linked_user(
+        &self,
+        identity: &ExternalIdentity,
+    ) -> Result<FullUser<UserMetadata>, AuthError> {
+        let username = if self
+            .auth
+            .create_user(identity.subject.clone())
+            .await
+            .is_ok()
+        {
+            identity.subject.clone()
+        } else {
+            let username = format!("user-{}", &dorsal::utility::random_id()[..16]);
+            self.auth.create_user(username.clone()).await?;
+            username
+        };
+
+        if self
+            .oauth
+            .link_identity(
+                identity.provider.clone(),
+                identity.subject.clone(),
+                username.clone(),
+            )
+            .await
+            .is_err()
+        {
+            // linked by a concurrent login
+            let _ = self.auth.erase_user(username).await;
+            return Err(AuthError::Other);
+        }
+
+        self.auth.get_user_by_username(username).await
+    }
 }
@@ -97,7 +97,10 @@ async fn main() -> std::io::Result<()> {
             // POST api
             .service(crate::api::auth::callback_request)
             // GET api
+            .service(crate::api::auth::login_request)
             .service(crate::api::auth::logout)
+            .service(crate::api::auth::refresh_request)
+            .service(crate::api::notifications::stream_request)
             // GET root
             .service(crate::pages::home::home_request)
     })
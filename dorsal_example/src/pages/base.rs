@@ -30,33 +30,35 @@ pub async fn check_auth_status(
 ) -> (
     String,
     Option<actix_web::cookie::Cookie<'static>>,
-    Option<dorsal::DefaultReturn<Option<dorsal::db::special::auth_db::FullUser<String>>>>,
+    Option<dorsal::token::TokenClaims>,
 ) {
     // verify auth status
     let token_cookie = req.cookie("__Secure-Token");
-    let mut set_cookie: &str = "";
-
-    let mut token_user: Option<
-        dorsal::DefaultReturn<Option<dorsal::db::special::auth_db::FullUser<String>>>,
-    > = if token_cookie.is_some() {
-        Option::Some(
-            data.db
-                .auth
-                .get_user_by_unhashed(token_cookie.as_ref().unwrap().value().to_string()) // if the user is returned, that means the ID is valid
-                .await,
-        )
+    let mut set_cookie = String::new();
+
+    let token_user = if token_cookie.is_some() {
+        let token = token_cookie.as_ref().unwrap().value().to_string();
+
+        match data.db.verify_token(token.clone()) {
+            // if the claims are returned, that means the token is valid
+            Ok(claims) => Option::Some(claims),
+            // expired tokens are refreshed (the user is checked again)
+            Err(_) => match data.db.refresh_token(token).await {
+                Ok((token, cookie)) => {
+                    set_cookie = cookie;
+                    data.db.verify_token(token).ok()
+                }
+                Err(_) => {
+                    // make sure user exists, refresh token if not
+                    set_cookie = String::from("__Secure-Token=refresh; SameSite=Strict; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age=0");
+                    Option::None
+                }
+            },
+        }
     } else {
         Option::None
     };
 
-    if token_user.is_some() {
-        // make sure user exists, refresh token if not
-        if token_user.as_ref().unwrap().success == false {
-            set_cookie = "__Secure-Token=refresh; SameSite=Strict; Secure; Path=/; HostOnly=true; HttpOnly=true; Max-Age=0";
-            token_user = Option::None;
-        }
-    }
-
     // return
-    (set_cookie.to_string(), token_cookie, token_user)
+    (set_cookie, token_cookie, token_user)
 }
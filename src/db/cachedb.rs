@@ -58,6 +58,38 @@ impl CacheDB {
         true
     }
 
+    /// Set a cache object by its identifier and content, removing it after `seconds`
+    ///
+    /// # Arguments:
+    /// * `id` - `String` of the object's id
+    /// * `content` - `String` of the object's content
+    /// * `seconds` - time until the object expires
+    pub async fn set_expiring(&self, id: String, content: String, seconds: u64) -> bool {
+        // set
+        let mut c = self.get_con().await;
+        let res: Result<String, redis::RedisError> = c.set_ex(id, content, seconds);
+
+        if res.is_err() {
+            return false;
+        }
+
+        // return
+        true
+    }
+
+    /// Get a cache object by its identifier and remove it, atomically (so it can only be taken once)
+    ///
+    /// # Arguments:
+    /// * `id` - `String` of the object's id
+    pub async fn take(&self, id: String) -> Option<String> {
+        let mut c = self.get_con().await;
+        let res: Result<Option<String>, redis::RedisError> =
+            redis::cmd("GETDEL").arg(id).query(&mut c);
+
+        // return
+        res.ok().flatten()
+    }
+
     /// Update a cache object by its identifier and content
     ///
     /// # Arguments:
@@ -2,6 +2,7 @@
 //! Database handler for all database types
 use super::{
     cachedb::CacheDB,
+    pubsub::PubSub,
     sql::{create_db, Database, DatabaseOpts},
 };
 
@@ -23,12 +24,32 @@ pub struct DatabaseReturn {
     pub data: HashMap<String, String>,
 }
 
+/// The sqlx database driver selected by the enabled feature
+#[cfg(feature = "postgres")]
+pub type Driver = sqlx::Postgres;
+
+/// The sqlx database driver selected by the enabled feature
+#[cfg(feature = "mysql")]
+pub type Driver = sqlx::MySql;
+
+/// The sqlx database driver selected by the enabled feature
+#[cfg(feature = "sqlite")]
+pub type Driver = sqlx::Sqlite;
+
+/// A row returned by [`Driver`]
+pub type DriverRow = <Driver as sqlx::Database>::Row;
+
+/// A query for [`Driver`], created with [`sqlx::query`]
+pub type DriverQuery<'q> =
+    sqlx::query::Query<'q, Driver, <Driver as sqlx::database::HasArguments<'q>>::Arguments>;
+
 #[derive(Clone)]
 #[cfg(feature = "postgres")]
 pub struct StarterDatabase {
     pub db: Database<sqlx::PgPool>,
     pub options: DatabaseOpts,
     pub cachedb: CacheDB,
+    pub pubsub: PubSub,
 }
 
 #[derive(Clone)]
@@ -37,6 +58,7 @@ pub struct StarterDatabase {
     pub db: Database<sqlx::MySqlPool>,
     pub options: DatabaseOpts,
     pub cachedb: CacheDB,
+    pub pubsub: PubSub,
 }
 
 #[derive(Clone)]
@@ -45,79 +67,203 @@ pub struct StarterDatabase {
     pub db: Database<sqlx::SqlitePool>,
     pub options: DatabaseOpts,
     pub cachedb: CacheDB,
+    pub pubsub: PubSub,
 }
 
 impl StarterDatabase {
     pub async fn new(options: DatabaseOpts) -> StarterDatabase {
+        let cachedb = CacheDB::new().await;
+
         StarterDatabase {
             db: create_db(options.clone()).await,
             options,
-            cachedb: CacheDB::new().await,
+            pubsub: PubSub::new(&cachedb),
+            cachedb,
         }
     }
 
-    #[cfg(feature = "sqlite")]
-    pub fn textify_row(&self, row: sqlx::sqlite::SqliteRow) -> DatabaseReturn {
-        // get all columns
-        let columns = row.columns();
+    /// Create an index on the given table if it doesn't already exist
+    ///
+    /// # Arguments:
+    /// * `table` - `&str` of the table's name
+    /// * `name` - `&str` of the index's name
+    /// * `columns` - names of the indexed columns
+    pub async fn create_index(&self, table: &str, name: &str, columns: &[&str]) -> bool {
+        self.create_index_of_kind(table, name, columns, "INDEX")
+            .await
+    }
 
-        // create output
-        let mut out: HashMap<String, String> = HashMap::new();
+    /// Create a unique index on the given table if it doesn't already exist
+    ///
+    /// # Arguments:
+    /// * `table` - `&str` of the table's name
+    /// * `name` - `&str` of the index's name
+    /// * `columns` - names of the indexed columns
+    pub async fn create_unique_index(&self, table: &str, name: &str, columns: &[&str]) -> bool {
+        self.create_index_of_kind(table, name, columns, "UNIQUE INDEX")
+            .await
+    }
+
+    /// Create an index (`kind` is "INDEX" or "UNIQUE INDEX") if it doesn't already exist
+    async fn create_index_of_kind(
+        &self,
+        table: &str,
+        name: &str,
+        columns: &[&str],
+        kind: &str,
+    ) -> bool {
+        let c = &self.db.client;
+        let columns = columns
+            .iter()
+            .map(|c| format!("\"{c}\""))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        if self.db._type == "mysql" {
+            // mysql doesn't support "IF NOT EXISTS" for indexes
+            let exists = match sqlx::query("SELECT COUNT(*) AS \"count\" FROM information_schema.statistics WHERE table_schema = DATABASE() AND table_name = ? AND index_name = ?")
+                .bind::<&str>(table)
+                .bind::<&str>(name)
+                .fetch_one(c)
+                .await
+            {
+                Ok(r) => r.try_get::<i64, _>("count").unwrap_or(0) > 0,
+                Err(_) => return false,
+            };
 
-        for column in columns {
-            let value = row.get(column.name());
-            out.insert(column.name().to_string(), value);
+            if exists {
+                return true;
+            }
+
+            return sqlx::query(&format!(
+                "CREATE {kind} \"{name}\" ON \"{table}\" ({columns})"
+            ))
+            .execute(c)
+            .await
+            .is_ok();
         }
 
-        // return
-        return DatabaseReturn { data: out };
+        sqlx::query(&format!(
+            "CREATE {kind} IF NOT EXISTS \"{name}\" ON \"{table}\" ({columns})"
+        ))
+        .execute(c)
+        .await
+        .is_ok()
     }
 
-    #[cfg(feature = "postgres")]
-    pub fn textify_row(&self, row: sqlx::postgres::PgRow) -> DatabaseReturn {
-        // get all columns
-        let columns = row.columns();
+    /// Convert the `timestamp` column of a table created before timestamps were
+    /// stored as `BIGINT` (they used to be stored as text)
+    ///
+    /// Sqlite can't change the type of a column, so the table is renamed, created again
+    /// using `create` (which must create the table with the same column order) and
+    /// then filled with the old rows.
+    ///
+    /// # Arguments:
+    /// * `table` - `&str` of the table's name
+    /// * `create` - `&str` of the table's `CREATE TABLE` query
+    pub async fn migrate_timestamp_column(&self, table: &str, create: &str) -> bool {
+        let c = &self.db.client;
 
-        // create output
-        let mut out: HashMap<String, String> = HashMap::new();
+        if self.db._type == "postgres" {
+            return sqlx::query(&format!("ALTER TABLE \"{table}\" ALTER COLUMN \"timestamp\" TYPE BIGINT USING \"timestamp\"::BIGINT"))
+                .execute(c)
+                .await
+                .is_ok();
+        } else if self.db._type == "mysql" {
+            return sqlx::query(&format!(
+                "ALTER TABLE \"{table}\" MODIFY \"timestamp\" BIGINT"
+            ))
+            .execute(c)
+            .await
+            .is_ok();
+        }
 
-        for column in columns {
-            let value = row.get(column.name());
-            out.insert(column.name().to_string(), value);
+        // sqlite
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return false,
+        };
+
+        for query in [
+            format!("ALTER TABLE \"{table}\" RENAME TO \"{table}_old\""),
+            create.to_string(),
+            // text that looks like an integer is stored as an integer in integer columns
+            format!("INSERT INTO \"{table}\" SELECT * FROM \"{table}_old\""),
+            format!("DROP TABLE \"{table}_old\""),
+        ] {
+            if sqlx::query(&query)
+                .execute(&mut *transaction)
+                .await
+                .is_err()
+            {
+                return false;
+            }
         }
 
-        // return
-        return DatabaseReturn { data: out };
+        transaction.commit().await.is_ok()
+    }
+
+    /// Fetch a single row and decode it into `T`
+    ///
+    /// # Arguments:
+    /// * `query` - the query to run, with its arguments already bound
+    ///
+    /// # Example:
+    /// ```ignore
+    /// let paste = db
+    ///     .fetch_one_as::<Paste>(sqlx::query("SELECT * FROM \"Pastes\" WHERE \"url\" = ?").bind(url))
+    ///     .await?;
+    /// ```
+    pub async fn fetch_one_as<'q, T>(&self, query: DriverQuery<'q>) -> sqlx::Result<T>
+    where
+        T: for<'r> sqlx::FromRow<'r, DriverRow> + Send + Unpin,
+    {
+        let row = query.fetch_one(&self.db.client).await?;
+        T::from_row(&row)
     }
 
-    #[cfg(feature = "mysql")]
-    pub fn textify_row(&self, row: sqlx::mysql::MySqlRow) -> DatabaseReturn {
-        // get all columns
-        let columns = row.columns();
+    /// Fetch all rows and decode them into `T`
+    ///
+    /// # Arguments:
+    /// * `query` - the query to run, with its arguments already bound
+    pub async fn fetch_all_as<'q, T>(&self, query: DriverQuery<'q>) -> sqlx::Result<Vec<T>>
+    where
+        T: for<'r> sqlx::FromRow<'r, DriverRow> + Send + Unpin,
+    {
+        let rows = query.fetch_all(&self.db.client).await?;
+        rows.iter().map(|row| T::from_row(row)).collect()
+    }
 
+    /// Convert every column of a row into a `String`
+    ///
+    /// `NULL` columns and columns of unsupported types are left out.
+    #[deprecated(note = "derive `sqlx::FromRow` and use `fetch_one_as` or `fetch_all_as` instead")]
+    pub fn textify_row(&self, row: DriverRow) -> DatabaseReturn {
         // create output
         let mut out: HashMap<String, String> = HashMap::new();
 
-        for column in columns {
-            let value = row.try_get::<Vec<u8>, _>(column.name());
-
-            if value.is_ok() {
-                // returned bytes instead of text :(
-                // we're going to convert this to a string and then add it to the output!
-                out.insert(
-                    column.name().to_string(),
-                    std::str::from_utf8(value.unwrap().as_slice())
-                        .unwrap()
-                        .to_string(),
-                );
+        for column in row.columns() {
+            let name = column.name();
+
+            let value = if let Ok(Some(v)) = row.try_get::<Option<String>, _>(name) {
+                v
+            } else if let Ok(Some(v)) = row.try_get::<Option<i64>, _>(name) {
+                v.to_string()
+            } else if let Ok(Some(v)) = row.try_get::<Option<i32>, _>(name) {
+                v.to_string()
+            } else if let Ok(Some(v)) = row.try_get::<Option<f64>, _>(name) {
+                v.to_string()
+            } else if let Ok(Some(v)) = row.try_get::<Option<Vec<u8>>, _>(name) {
+                // mysql can return text columns as bytes
+                String::from_utf8_lossy(&v).to_string()
             } else {
-                // already text
-                let value = row.get(column.name());
-                out.insert(column.name().to_string(), value);
-            }
+                continue;
+            };
+
+            out.insert(name.to_string(), value);
         }
 
         // return
-        return DatabaseReturn { data: out };
+        DatabaseReturn { data: out }
     }
 }
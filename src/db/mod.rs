@@ -1,4 +1,5 @@
 pub mod cachedb;
 pub mod db;
+pub mod pubsub;
 pub mod special;
 pub mod sql;
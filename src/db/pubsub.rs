@@ -0,0 +1,126 @@
+//! # PubSub
+//!
+//! Message fan-out between (and within) server processes.
+//!
+//! Uses Redis pub/sub when the [`CacheDB`] Redis server is reachable, and an in-process
+//! broadcast channel otherwise (which only reaches subscribers in the same process).
+//!
+//! Channels should follow the same format as cache identifiers: `TYPE_OF_OBJECT:CHANNEL`.
+use super::cachedb::CacheDB;
+
+use futures_util::stream::{self, BoxStream, StreamExt};
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use std::sync::Arc;
+use tokio::sync::{broadcast, Mutex};
+
+#[derive(Clone)]
+pub enum PubSub {
+    /// Redis client, and the connection messages are published through (created on first use)
+    Redis(redis::Client, Arc<Mutex<Option<MultiplexedConnection>>>),
+    /// In-process channel of `(channel, message)` pairs
+    Local(broadcast::Sender<(String, String)>),
+}
+
+impl PubSub {
+    /// Create a [`PubSub`] backed by the given [`CacheDB`], falling back to an
+    /// in-process channel if Redis can't be reached
+    ///
+    /// # Arguments:
+    /// * `cachedb` - [`CacheDB`]
+    pub fn new(cachedb: &CacheDB) -> PubSub {
+        match cachedb.client.get_connection() {
+            Ok(_) => PubSub::Redis(cachedb.client.clone(), Arc::new(Mutex::new(None))),
+            Err(_) => PubSub::local(),
+        }
+    }
+
+    /// Create an in-process [`PubSub`]
+    pub fn local() -> PubSub {
+        PubSub::Local(broadcast::channel(1024).0)
+    }
+
+    /// Publish a message to a channel
+    ///
+    /// # Arguments:
+    /// * `channel` - `String` of the channel's name
+    /// * `message` - `String` of the message
+    pub async fn publish(&self, channel: String, message: String) -> bool {
+        match self {
+            PubSub::Redis(client, connection) => {
+                let mut c = {
+                    let mut connection = connection.lock().await;
+
+                    match connection.as_ref() {
+                        Some(c) => c.clone(),
+                        None => match client.get_multiplexed_async_connection().await {
+                            Ok(c) => connection.insert(c).clone(),
+                            Err(_) => return false,
+                        },
+                    }
+                };
+
+                let res: Result<i64, redis::RedisError> = c.publish(channel, message).await;
+
+                if res.is_err() {
+                    // connect again next time
+                    *connection.lock().await = None;
+                }
+
+                res.is_ok()
+            }
+            PubSub::Local(sender) => {
+                // sending only fails when there are no subscribers
+                let _ = sender.send((channel, message));
+                true
+            }
+        }
+    }
+
+    /// Subscribe to a channel, returning a stream of its messages
+    ///
+    /// # Arguments:
+    /// * `channel` - `String` of the channel's name
+    pub async fn subscribe(&self, channel: String) -> Option<BoxStream<'static, String>> {
+        match self {
+            PubSub::Redis(client, _) => {
+                let mut pubsub = match client.get_async_pubsub().await {
+                    Ok(p) => p,
+                    Err(_) => return None,
+                };
+
+                if pubsub.subscribe(channel).await.is_err() {
+                    return None;
+                }
+
+                Some(
+                    pubsub
+                        .into_on_message()
+                        .filter_map(|msg| async move { msg.get_payload::<String>().ok() })
+                        .boxed(),
+                )
+            }
+            PubSub::Local(sender) => Some(
+                stream::unfold(sender.subscribe(), move |mut receiver| {
+                    let channel = channel.clone();
+
+                    async move {
+                        loop {
+                            match receiver.recv().await {
+                                Ok((c, message)) => {
+                                    if c == channel {
+                                        return Some((message, receiver));
+                                    }
+                                }
+                                // slow subscribers skip missed messages
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(broadcast::error::RecvError::Closed) => return None,
+                            }
+                        }
+                    }
+                })
+                .boxed(),
+            ),
+        }
+    }
+}
@@ -1,5 +1,11 @@
-use crate::{utility, DefaultReturn, StarterDatabase};
-use serde::{Deserialize, Serialize};
+use super::log_db::{DatabaseOptions as LogDatabaseOptions, Log, LogKind};
+use super::notification_db::{Notification, NotificationPreferences};
+#[cfg(feature = "webhooks")]
+use super::webhook_db::WebhookDatabase;
+use crate::token::{TokenClaims, TokenKeyring};
+use crate::{utility, DefaultReturn, LogDatabase, StarterDatabase};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::Row;
 
 // guppy authentication structs
 #[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
@@ -16,6 +22,35 @@ pub struct UserState<M> {
     pub metadata: M,
 }
 
+impl<'r, R, M> sqlx::FromRow<'r, R> for UserState<M>
+where
+    R: Row,
+    &'r str: sqlx::ColumnIndex<R>,
+    String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    M: DeserializeOwned,
+{
+    fn from_row(row: &'r R) -> sqlx::Result<Self> {
+        let metadata = row.try_get::<String, _>("metadata")?;
+
+        Ok(UserState {
+            username: row.try_get("username")?,
+            id_hashed: row.try_get("id_hashed")?,
+            role: row.try_get("role")?,
+            timestamp: row.try_get::<i64, _>("timestamp")? as u128,
+            metadata: match serde_json::from_str(&metadata) {
+                Ok(m) => m,
+                Err(e) => {
+                    return Err(sqlx::Error::ColumnDecode {
+                        index: String::from("metadata"),
+                        source: Box::new(e),
+                    })
+                }
+            },
+        })
+    }
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct RoleLevel {
     /// Marks the level of the role, 0 should always be member
@@ -51,6 +86,14 @@ impl Default for RoleLevelLog {
     }
 }
 
+/// [`LogKind`] of stored [`RoleLevel`]s
+pub struct LevelLog;
+
+impl LogKind for LevelLog {
+    const LOGTYPE: &'static str = "level";
+    type Payload = RoleLevel;
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct UserMetadata {
     /// User's "about" section (markdown supported)
@@ -61,9 +104,89 @@ pub struct UserMetadata {
     pub secondary_token: Option<String>,
     /// User display name
     pub nickname: Option<String>,
+    /// Username of the user who invited this user
+    pub invited_by: Option<String>,
+    /// User's notification preferences
+    #[serde(default)]
+    pub notifications: NotificationPreferences,
+    /// User's preferred locale (e.g. "en"), used to render notifications
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// User's email address, used to send notification digests
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Time the last notification digest was sent to the user (see [`crate::NotificationDatabase::send_digests`])
+    #[serde(default)]
+    pub last_digest: Option<u128>,
+    /// Stateless tokens issued before this time (seconds since epoch) can't be refreshed
+    #[serde(default)]
+    pub tokens_valid_after: Option<u64>,
     // pub permissions: Vec<String>,
 }
 
+// audit
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// A security-relevant account action
+pub enum AuditAction {
+    /// The user's role was changed
+    #[default]
+    RoleChange,
+    /// The user's secondary token was replaced
+    TokenRotation,
+    /// The user was banned
+    Ban,
+    /// The user's stateless tokens were revoked
+    TokenRevocation,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A single audit trail entry, stored as a log with the `audit` logtype
+pub struct AuditEntry {
+    /// Username of the user who performed the action
+    pub actor: String,
+    /// Username of the user the action was performed on
+    pub target: String,
+    pub action: AuditAction,
+    /// Value before the action (never contains secrets)
+    pub before: Option<String>,
+    /// Value after the action (never contains secrets)
+    pub after: Option<String>,
+    /// IP address the action was performed from
+    pub ip: Option<String>,
+    // dates
+    pub timestamp: u128,
+}
+
+/// [`LogKind`] of stored [`AuditEntry`]s
+pub struct AuditLog;
+
+impl LogKind for AuditLog {
+    const LOGTYPE: &'static str = "audit";
+    type Payload = AuditEntry;
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// Who performed an audited action, and where they performed it from
+pub struct AuditContext {
+    /// Username of the user performing the action
+    pub actor: String,
+    /// IP address of the request
+    pub ip: Option<String>,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq)]
+/// All data stored about a user, for privacy requests
+pub struct UserDataExport {
+    pub user: UserState<UserMetadata>,
+    /// Notifications sent to the user
+    pub notifications: Vec<Notification>,
+    /// Audit trail entries that reference the user
+    pub audit: Vec<Log>,
+    /// Any other logs that reference the user
+    pub logs: Vec<Log>,
+}
+
 // ...
 /// Auth database errors
 #[derive(Debug)]
@@ -71,6 +194,7 @@ pub enum AuthError {
     ValueError,
     NotFound,
     Banned,
+    InvalidToken,
     Other,
 }
 
@@ -81,6 +205,7 @@ impl AuthError {
             ValueError => String::from("One of the field values given is invalid."),
             NotFound => String::from("User could not be found."),
             Banned => String::from("User is banned."),
+            InvalidToken => String::from("Token is invalid or has expired."),
             _ => String::from("An unspecified error has occured."),
         }
     }
@@ -98,6 +223,14 @@ impl<T: Default> Into<DefaultReturn<T>> for AuthError {
 
 pub type Result<T> = std::result::Result<T, AuthError>;
 
+/// Convert an error from fetching a user row into an [`AuthError`]
+fn fetch_error(e: sqlx::Error) -> AuthError {
+    match e {
+        sqlx::Error::ColumnDecode { .. } => AuthError::ValueError,
+        _ => AuthError::NotFound,
+    }
+}
+
 // ...
 #[derive(Clone)]
 pub struct DatabaseOptions {
@@ -105,10 +238,18 @@ pub struct DatabaseOptions {
     pub table: String,
     /// Table used for logs operations
     pub logs_table: String,
+    /// Table notifications are stored in, none if [`crate::NotificationDatabase`] isn't used
+    pub notifications_table: Option<String>,
+    /// Table external identities are stored in, none if `OAuthDatabase` isn't used
+    pub identities_table: Option<String>,
     /// The prefix used in redis keys
     pub prefix: String,
     /// The prefix used for log redis keys (levels only)
     pub logs_prefix: String,
+    /// The prefix used for notification redis keys (unread counts only)
+    pub notifications_prefix: String,
+    /// Keys used to sign stateless session tokens (stateless tokens are disabled if none)
+    pub tokens: Option<TokenKeyring>,
 }
 
 impl Default for DatabaseOptions {
@@ -116,8 +257,12 @@ impl Default for DatabaseOptions {
         Self {
             table: String::from("Users"),
             logs_table: String::from("Logs"),
+            notifications_table: Some(String::from("Notifications")),
+            identities_table: None,
             prefix: String::from("user"),
             logs_prefix: String::from("level"),
+            notifications_prefix: String::from("notification"),
+            tokens: None,
         }
     }
 }
@@ -127,11 +272,98 @@ impl Default for DatabaseOptions {
 pub struct AuthDatabase {
     pub base: StarterDatabase,
     pub options: DatabaseOptions,
+    /// Logs that audit entries are created through (see [`AuthDatabase::with_logs`])
+    pub logs: Option<LogDatabase>,
+    /// Webhooks that account changes are sent to (see [`AuthDatabase::with_webhooks`])
+    #[cfg(feature = "webhooks")]
+    pub webhooks: Option<WebhookDatabase>,
 }
 
 impl AuthDatabase {
     pub async fn new(base: StarterDatabase, options: DatabaseOptions) -> AuthDatabase {
-        AuthDatabase { base, options }
+        AuthDatabase {
+            base,
+            options,
+            logs: None,
+            #[cfg(feature = "webhooks")]
+            webhooks: None,
+        }
+    }
+
+    /// Create audit entries through the given [`LogDatabase`], so they use its options
+    /// (its table should be the same as `logs_table`)
+    ///
+    /// # Arguments:
+    /// * `logs` - [`LogDatabase`]
+    pub fn with_logs(mut self, logs: LogDatabase) -> Self {
+        self.logs = Some(logs);
+        self
+    }
+
+    /// Send account changes (every [`AuditEntry`]) to the target user's webhooks
+    ///
+    /// The event of each entry is `user.{action}` (ex: "user.role_change").
+    ///
+    /// # Arguments:
+    /// * `webhooks` - [`WebhookDatabase`]
+    #[cfg(feature = "webhooks")]
+    pub fn with_webhooks(mut self, webhooks: WebhookDatabase) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Create the users table (and its indexes) if it doesn't already exist
+    ///
+    /// The logs table is created by [`LogDatabase::init`].
+    pub async fn init(&self) -> bool {
+        let c = &self.base.db.client;
+        let table = &self.options.table;
+
+        if sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{table}\" (
+                username VARCHAR(255),
+                id_hashed VARCHAR(255),
+                role VARCHAR(255),
+                timestamp BIGINT,
+                metadata TEXT
+            )"
+        ))
+        .execute(c)
+        .await
+        .is_err()
+        {
+            return false;
+        }
+
+        // usernames are unique, even if users are created concurrently
+        self.base
+            .create_unique_index(table, &format!("{table}_username_unique"), &["username"])
+            .await
+            && self
+                .base
+                .create_index(table, &format!("{table}_id_hashed"), &["id_hashed"])
+                .await
+    }
+
+    /// Convert a users table created before timestamps were stored as `BIGINT`
+    pub async fn migrate(&self) -> bool {
+        let table = &self.options.table;
+
+        self.base
+            .migrate_timestamp_column(
+                table,
+                &format!(
+                    "CREATE TABLE \"{table}\" (
+                        username VARCHAR(255),
+                        id_hashed VARCHAR(255),
+                        role VARCHAR(255),
+                        timestamp BIGINT,
+                        metadata TEXT
+                    )"
+                ),
+            )
+            .await
+            && self.init().await
     }
 
     // users
@@ -155,35 +387,22 @@ impl AuthDatabase {
             )
         };
 
-        let c = &self.base.db.client;
-        let row = match sqlx::query(&query)
-            .bind::<&String>(&hashed)
-            .fetch_one(c)
+        let user = match self
+            .base
+            .fetch_one_as::<UserState<UserMetadata>>(sqlx::query(&query).bind::<&String>(&hashed))
             .await
         {
-            Ok(u) => self.base.textify_row(u).data,
-            Err(_) => return Err(AuthError::NotFound),
+            Ok(u) => u,
+            Err(e) => return Err(fetch_error(e)),
         };
 
         // ...
-        let role = row.get("role").unwrap().to_string();
+        let role = user.role.clone();
 
         if role == "banned" {
             return Err(AuthError::Banned);
         }
 
-        // ...
-        let user = UserState {
-            username: row.get("username").unwrap().to_string(),
-            id_hashed: row.get("id_hashed").unwrap().to_string(),
-            role: role.clone(),
-            timestamp: row.get("timestamp").unwrap().parse::<u128>().unwrap(),
-            metadata: match serde_json::from_str(row.get("metadata").unwrap()) {
-                Ok(m) => m,
-                Err(_) => return Err(AuthError::Banned),
-            },
-        };
-
         // fetch level from role
         let level = self.get_level_by_role(role).await;
 
@@ -229,38 +448,25 @@ impl AuthDatabase {
             )
         };
 
-        let c = &self.base.db.client;
-        let row = match sqlx::query(&query)
-            .bind::<&String>(&format!(
+        let user = match self
+            .base
+            .fetch_one_as::<UserState<UserMetadata>>(sqlx::query(&query).bind::<&String>(&format!(
                 "%\"secondary_token\":\"{}\"%",
                 crate::utility::hash(unhashed)
-            ))
-            .fetch_one(c)
+            )))
             .await
         {
-            Ok(r) => self.base.textify_row(r).data,
-            Err(_) => return Err(AuthError::NotFound),
+            Ok(u) => u,
+            Err(e) => return Err(fetch_error(e)),
         };
 
         // ...
-        let role = row.get("role").unwrap().to_string();
+        let role = user.role.clone();
 
         if role == "banned" {
             return Err(AuthError::Banned);
         }
 
-        // ...
-        let user = UserState {
-            username: row.get("username").unwrap().to_string(),
-            id_hashed: row.get("id_hashed").unwrap().to_string(),
-            role: role.clone(),
-            timestamp: row.get("timestamp").unwrap().parse::<u128>().unwrap(),
-            metadata: match serde_json::from_str(row.get("metadata").unwrap()) {
-                Ok(m) => m,
-                Err(_) => return Err(AuthError::ValueError),
-            },
-        };
-
         // fetch level from role
         let level = self.get_level_by_role(role).await;
 
@@ -319,37 +525,24 @@ impl AuthDatabase {
             )
         };
 
-        let c = &self.base.db.client;
-        let row = match sqlx::query(&query)
-            .bind::<&String>(&username)
-            .fetch_one(c)
+        let user = match self
+            .base
+            .fetch_one_as::<UserState<UserMetadata>>(sqlx::query(&query).bind::<&String>(&username))
             .await
         {
-            Ok(r) => self.base.textify_row(r).data,
-            Err(_) => return Err(AuthError::NotFound),
+            Ok(u) => u,
+            Err(e) => return Err(fetch_error(e)),
         };
 
         // ...
-        let role = row.get("role").unwrap().to_string();
-
-        if role == "banned" {
+        if user.role == "banned" {
             return Err(AuthError::NotFound);
         }
 
         // fetch level from role
-        let level = self.get_level_by_role(role.clone()).await;
+        let level = self.get_level_by_role(user.role.clone()).await;
 
         // store in cache
-        let user = UserState {
-            username: row.get("username").unwrap().to_string(),
-            id_hashed: row.get("id_hashed").unwrap().to_string(),
-            role,
-            timestamp: row.get("timestamp").unwrap().parse::<u128>().unwrap(),
-            metadata: match serde_json::from_str(row.get("metadata").unwrap()) {
-                Ok(m) => m,
-                Err(_) => return Err(AuthError::ValueError),
-            },
-        };
 
         self.base
             .cachedb
@@ -395,13 +588,14 @@ impl AuthDatabase {
             )
         };
 
-        let c = &self.base.db.client;
-        let row = match sqlx::query(&query)
-            .bind::<&String>(&format!("%\"name\":\"{}\"%", name))
-            .fetch_one(c)
+        let log = match self
+            .base
+            .fetch_one_as::<Log>(
+                sqlx::query(&query).bind::<&String>(&format!("%\"name\":\"{}\"%", name)),
+            )
             .await
         {
-            Ok(r) => self.base.textify_row(r).data,
+            Ok(l) => l,
             Err(_) => {
                 // return default if not found
                 return RoleLevelLog::default();
@@ -409,10 +603,10 @@ impl AuthDatabase {
         };
 
         // store in cache
-        let id = row.get("id").unwrap().to_string();
-        let level = serde_json::from_str::<RoleLevel>(row.get("content").unwrap()).unwrap();
-
-        let level = RoleLevelLog { id, level };
+        let level = RoleLevelLog {
+            id: log.id,
+            level: serde_json::from_str::<RoleLevel>(&log.content).unwrap(),
+        };
         self.base
             .cachedb
             .set(
@@ -424,4 +618,757 @@ impl AuthDatabase {
         // return
         return level;
     }
+
+    /// Check if the given `username` is valid for a new user
+    ///
+    /// # Arguments:
+    /// * `username` - `&str` of the username
+    pub fn validate_username(username: &str) -> Result<()> {
+        if username.is_empty()
+            | (username.len() > 32)
+            | !username
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() | (c == '_') | (c == '-'))
+        {
+            return Err(AuthError::ValueError);
+        }
+
+        Ok(())
+    }
+
+    // SET
+    /// Create a new user given their `username`
+    ///
+    /// Returns the new user's **unhashed** ID, which they use to login.
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    pub async fn create_user(&self, username: String) -> Result<String> {
+        Self::validate_username(&username)?;
+
+        // make sure user doesn't already exist
+        if self.get_user_role(username.clone()).await.is_ok() {
+            return Err(AuthError::ValueError);
+        }
+
+        // ...
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "INSERT INTO \"{}\" VALUES (?, ?, ?, ?, ?)",
+                self.options.table
+            )
+        } else {
+            format!(
+                "INSERT INTO \"{}\" VALUES ($1, $2, $3, $4, $5)",
+                self.options.table
+            )
+        };
+
+        let user_id: String = utility::uuid();
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<&String>(&username)
+            .bind::<String>(utility::hash(user_id.clone()))
+            .bind::<&str>("member")
+            .bind::<i64>(utility::unix_epoch_timestamp() as i64)
+            .bind::<String>(
+                serde_json::to_string::<UserMetadata>(&UserMetadata::default()).unwrap(),
+            )
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(user_id),
+            Err(_) => Err(AuthError::Other),
+        }
+    }
+
+    /// Get the [`LogDatabase`] that shares this database's logs table
+    ///
+    /// Uses the default log options (with `logs_table`) unless [`AuthDatabase::with_logs`] was used.
+    fn logs(&self) -> LogDatabase {
+        match self.logs {
+            Some(ref logs) => logs.clone(),
+            None => LogDatabase {
+                base: self.base.clone(),
+                options: LogDatabaseOptions {
+                    table: self.options.logs_table.clone(),
+                    ..Default::default()
+                },
+            },
+        }
+    }
+
+    /// Get the role of a user by their username (including banned users)
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    async fn get_user_role(&self, username: String) -> Result<String> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT \"role\" FROM \"{}\" WHERE \"username\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "SELECT \"role\" FROM \"{}\" WHERE \"username\" = $1",
+                self.options.table
+            )
+        };
+
+        match self
+            .base
+            .fetch_one_as::<(String,)>(sqlx::query(&query).bind::<&String>(&username))
+            .await
+        {
+            Ok((role,)) => Ok(role),
+            Err(_) => Err(AuthError::NotFound),
+        }
+    }
+
+    // audit
+
+    // GET
+    /// Get the [`AuditEntry`]s that target the given `username`, newest first
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    /// * `offset` - optional pagination offset (pages are 50 entries)
+    pub async fn get_user_audit_log(
+        &self,
+        username: String,
+        offset: Option<i32>,
+    ) -> Result<Vec<AuditEntry>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("SELECT * FROM \"{}\" WHERE \"logtype\" = ? AND \"content\" LIKE ? ESCAPE '!' ORDER BY \"timestamp\" DESC LIMIT 50 OFFSET ?", self.options.logs_table)
+        } else {
+            format!("SELECT * FROM \"{}\" WHERE \"logtype\" = $1 AND \"content\" LIKE $2 ESCAPE '!' ORDER BY \"timestamp\" DESC LIMIT 50 OFFSET $3", self.options.logs_table)
+        };
+
+        let target = utility::escape_like(&serde_json::to_string(&username).unwrap());
+
+        let logs = match self
+            .base
+            .fetch_all_as::<Log>(
+                sqlx::query(&query)
+                    .bind::<&str>(AuditLog::LOGTYPE)
+                    .bind::<String>(format!("%\"target\":{target}%"))
+                    .bind(offset.unwrap_or(0)),
+            )
+            .await
+        {
+            Ok(l) => l,
+            Err(_) => return Err(AuthError::Other),
+        };
+
+        // ...
+        let mut output: Vec<AuditEntry> = Vec::new();
+
+        for log in logs {
+            match serde_json::from_str::<AuditEntry>(&log.content) {
+                Ok(e) => output.push(e),
+                Err(_) => return Err(AuthError::ValueError),
+            }
+        }
+
+        // return
+        Ok(output)
+    }
+
+    // SET
+    /// Record an [`AuditEntry`] in the logs table
+    ///
+    /// # Arguments:
+    /// * `ctx` - [`AuditContext`] of the action
+    /// * `target` - `String` of the target user's username
+    /// * `action` - [`AuditAction`]
+    /// * `before` - value before the action
+    /// * `after` - value after the action
+    pub async fn create_audit_entry(
+        &self,
+        ctx: AuditContext,
+        target: String,
+        action: AuditAction,
+        before: Option<String>,
+        after: Option<String>,
+    ) -> Result<()> {
+        let entry = AuditEntry {
+            actor: ctx.actor,
+            target,
+            action,
+            before,
+            after,
+            ip: ctx.ip,
+            timestamp: utility::unix_epoch_timestamp(),
+        };
+
+        if self
+            .logs()
+            .create_log(
+                AuditLog::LOGTYPE.to_string(),
+                serde_json::to_string::<AuditEntry>(&entry).unwrap(),
+            )
+            .await
+            .is_err()
+        {
+            return Err(AuthError::Other);
+        }
+
+        #[cfg(feature = "webhooks")]
+        if let Some(ref webhooks) = self.webhooks {
+            let event = format!(
+                "user.{}",
+                serde_json::to_value(&entry.action)
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+            );
+
+            // the actor's ip isn't sent to the target
+            let entry = AuditEntry { ip: None, ..entry };
+            let _ = webhooks
+                .dispatch(vec![entry.target.clone()], &event, &entry)
+                .await;
+        }
+
+        Ok(())
+    }
+
+    /// Update the metadata of a user by their username
+    ///
+    /// The metadata is read from the database (not the cache), changed and written back
+    /// in a single transaction, so concurrent updates of different fields don't overwrite
+    /// each other. Returns the updated metadata.
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    /// * `update` - function that changes the user's [`UserMetadata`]
+    pub async fn update_user_metadata<F>(&self, username: String, update: F) -> Result<UserMetadata>
+    where
+        F: FnOnce(&mut UserMetadata),
+    {
+        let table = &self.options.table;
+        let (select, query) = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql")
+        {
+            (
+                format!("SELECT \"metadata\" FROM \"{table}\" WHERE \"username\" = ?"),
+                format!("UPDATE \"{table}\" SET \"metadata\" = ? WHERE \"username\" = ?"),
+            )
+        } else {
+            (
+                format!("SELECT \"metadata\" FROM \"{table}\" WHERE \"username\" = $1"),
+                format!("UPDATE \"{table}\" SET \"metadata\" = $1 WHERE \"username\" = $2"),
+            )
+        };
+
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(AuthError::Other),
+        };
+
+        // lock the user until the transaction ends
+        let select = if self.base.db._type == "sqlite" {
+            // sqlite locks the whole database on the first write of a transaction
+            if sqlx::query(&format!(
+                "UPDATE \"{table}\" SET \"metadata\" = \"metadata\" WHERE \"username\" = ?"
+            ))
+            .bind::<&String>(&username)
+            .execute(&mut *transaction)
+            .await
+            .is_err()
+            {
+                return Err(AuthError::Other);
+            }
+
+            select
+        } else {
+            format!("{select} FOR UPDATE")
+        };
+
+        let mut metadata = match sqlx::query_as::<_, (String,)>(&select)
+            .bind::<&String>(&username)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(Some((m,))) => match serde_json::from_str::<UserMetadata>(&m) {
+                Ok(m) => m,
+                Err(_) => return Err(AuthError::Other),
+            },
+            Ok(None) => return Err(AuthError::NotFound),
+            Err(_) => return Err(AuthError::Other),
+        };
+
+        update(&mut metadata);
+
+        if sqlx::query(&query)
+            .bind::<String>(serde_json::to_string::<UserMetadata>(&metadata).unwrap())
+            .bind::<&String>(&username)
+            .execute(&mut *transaction)
+            .await
+            .is_err()
+        {
+            return Err(AuthError::Other);
+        }
+
+        if transaction.commit().await.is_err() {
+            return Err(AuthError::Other);
+        }
+
+        // update cache
+        self.base
+            .cachedb
+            .remove(format!("{}:{}", self.options.prefix, username))
+            .await;
+
+        // return
+        Ok(metadata)
+    }
+
+    /// Update the role of a user by their username, recording an [`AuditEntry`]
+    ///
+    /// # Arguments:
+    /// * `ctx` - [`AuditContext`] of the action
+    /// * `username` - `String` of the user's username
+    /// * `role` - `String` of the user's new role
+    pub async fn update_user_role(
+        &self,
+        ctx: AuditContext,
+        username: String,
+        role: String,
+    ) -> Result<()> {
+        // update user, only if their role wasn't changed since it was read
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "UPDATE \"{}\" SET \"role\" = ? WHERE \"username\" = ? AND \"role\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "UPDATE \"{}\" SET \"role\" = $1 WHERE \"username\" = $2 AND \"role\" = $3",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        let mut before = self.get_user_role(username.clone()).await?;
+
+        while before != role {
+            match sqlx::query(&query)
+                .bind::<&String>(&role)
+                .bind::<&String>(&username)
+                .bind::<&String>(&before)
+                .execute(c)
+                .await
+            {
+                Ok(r) if r.rows_affected() > 0 => break,
+                // changed by someone else, the entry records their role as the previous one
+                Ok(_) => before = self.get_user_role(username.clone()).await?,
+                Err(_) => return Err(AuthError::Other),
+            }
+        }
+
+        // update cache
+        self.base
+            .cachedb
+            .remove(format!("{}:{}", self.options.prefix, username))
+            .await;
+
+        // record
+        let action = if role == "banned" {
+            AuditAction::Ban
+        } else {
+            AuditAction::RoleChange
+        };
+
+        self.create_audit_entry(ctx, username, action, Some(before), Some(role))
+            .await
+    }
+
+    /// Ban a user by their username (sets their role to `banned`)
+    ///
+    /// # Arguments:
+    /// * `ctx` - [`AuditContext`] of the action
+    /// * `username` - `String` of the user's username
+    pub async fn ban_user(&self, ctx: AuditContext, username: String) -> Result<()> {
+        self.update_user_role(ctx, username, String::from("banned"))
+            .await
+    }
+
+    /// Replace the secondary token of a user by their username, recording an [`AuditEntry`]
+    ///
+    /// Returns the new **unhashed** secondary token; only its hash is stored.
+    ///
+    /// # Arguments:
+    /// * `ctx` - [`AuditContext`] of the action
+    /// * `username` - `String` of the user's username
+    pub async fn update_user_secondary_token(
+        &self,
+        ctx: AuditContext,
+        username: String,
+    ) -> Result<String> {
+        // make sure user exists
+        self.get_user_by_username(username.clone()).await?;
+
+        let token = utility::uuid();
+        let hashed = utility::hash(token.clone());
+
+        // update user
+        self.update_user_metadata(username.clone(), |m| m.secondary_token = Some(hashed))
+            .await?;
+
+        // record
+        self.create_audit_entry(ctx, username, AuditAction::TokenRotation, None, None)
+            .await?;
+
+        // return
+        Ok(token)
+    }
+
+    // tokens
+
+    /// Get the configured [`TokenKeyring`]
+    fn keyring(&self) -> Result<&TokenKeyring> {
+        match self.options.tokens {
+            Some(ref k) => Ok(k),
+            None => Err(AuthError::Other),
+        }
+    }
+
+    /// Issue a signed stateless token for the given user
+    ///
+    /// # Arguments:
+    /// * `user` - [`FullUser`] the token is for
+    pub fn issue_token(&self, user: &FullUser<UserMetadata>) -> Result<String> {
+        match self.keyring()?.issue(
+            user.user.username.clone(),
+            user.user.role.clone(),
+            user.level.elevation,
+        ) {
+            Ok(t) => Ok(t),
+            Err(_) => Err(AuthError::Other),
+        }
+    }
+
+    /// Verify a stateless token (no database access), returning its [`TokenClaims`]
+    ///
+    /// # Arguments:
+    /// * `token` - `&str` of the token
+    pub fn verify_token(&self, token: &str) -> Result<TokenClaims> {
+        match self.keyring()?.verify(token) {
+            Ok(c) => Ok(c),
+            Err(_) => Err(AuthError::InvalidToken),
+        }
+    }
+
+    /// Refresh a stateless token, returning a new token
+    ///
+    /// The user is fetched from the database again, so role changes, bans and
+    /// [`AuthDatabase::revoke_tokens`] apply. Expired tokens can be refreshed within
+    /// [`TokenKeyring::refresh_window`].
+    ///
+    /// # Arguments:
+    /// * `token` - `&str` of the token
+    pub async fn refresh_token(&self, token: &str) -> Result<String> {
+        let claims = match self.keyring()?.verify_refreshable(token) {
+            Ok(c) => c,
+            Err(_) => return Err(AuthError::InvalidToken),
+        };
+
+        // fetch user
+        let user = self.get_user_by_username(claims.sub).await?;
+
+        if user
+            .user
+            .metadata
+            .tokens_valid_after
+            .is_some_and(|t| claims.iat < t)
+        {
+            return Err(AuthError::InvalidToken);
+        }
+
+        // return
+        self.issue_token(&user)
+    }
+
+    /// Revoke every stateless token issued to a user so far, recording an [`AuditEntry`]
+    ///
+    /// Revoked tokens can't be refreshed, but [`AuthDatabase::verify_token`] doesn't access
+    /// the database, so they are accepted until they expire (see [`TokenKeyring::lifetime`]).
+    ///
+    /// # Arguments:
+    /// * `ctx` - [`AuditContext`] of the action
+    /// * `username` - `String` of the user's username
+    pub async fn revoke_tokens(&self, ctx: AuditContext, username: String) -> Result<()> {
+        // make sure user exists
+        self.get_user_by_username(username.clone()).await?;
+
+        // tokens issued in the same second as the revocation are revoked too
+        let valid_after = crate::token::now() + 1;
+
+        // update user
+        self.update_user_metadata(username.clone(), |m| {
+            m.tokens_valid_after = Some(valid_after)
+        })
+        .await?;
+
+        // record
+        self.create_audit_entry(ctx, username, AuditAction::TokenRevocation, None, None)
+            .await
+    }
+
+    // privacy
+
+    /// Get the [`Log`]s that reference the given `username` in one of [`USERNAME_KEYS`]
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    async fn get_logs_referencing(&self, username: String) -> Result<Vec<Log>> {
+        let conditions: Vec<String> =
+            if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+                USERNAME_KEYS
+                    .iter()
+                    .map(|_| String::from("\"content\" LIKE ? ESCAPE '!'"))
+                    .collect()
+            } else {
+                (1..=USERNAME_KEYS.len())
+                    .map(|i| format!("\"content\" LIKE ${i} ESCAPE '!'"))
+                    .collect()
+            };
+
+        let query = format!(
+            "SELECT * FROM \"{}\" WHERE {} ORDER BY \"timestamp\" DESC",
+            self.options.logs_table,
+            conditions.join(" OR ")
+        );
+
+        let value = utility::escape_like(&serde_json::to_string(&username).unwrap());
+        let mut query = sqlx::query(&query);
+
+        for key in USERNAME_KEYS {
+            query = query.bind::<String>(format!("%\"{key}\":{value}%"));
+        }
+
+        match self.base.fetch_all_as::<Log>(query).await {
+            // the pattern could also match inside of another value
+            Ok(l) => Ok(l
+                .into_iter()
+                .filter(|l| anonymize_content(&l.content, &username).is_some())
+                .collect()),
+            Err(_) => Err(AuthError::Other),
+        }
+    }
+
+    /// Gather all data stored about a user into a [`UserDataExport`]
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    pub async fn export_user_data(&self, username: String) -> Result<UserDataExport> {
+        // fetch user (banned users are included)
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"username\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"username\" = $1",
+                self.options.table
+            )
+        };
+
+        let user = match self
+            .base
+            .fetch_one_as::<UserState<UserMetadata>>(sqlx::query(&query).bind::<&String>(&username))
+            .await
+        {
+            Ok(u) => u,
+            Err(e) => return Err(fetch_error(e)),
+        };
+
+        // fetch logs
+        let mut export = UserDataExport {
+            user,
+            ..Default::default()
+        };
+
+        if let Some(ref table) = self.options.notifications_table {
+            let query: String = if (self.base.db._type == "sqlite")
+                | (self.base.db._type == "mysql")
+            {
+                format!(
+                    "SELECT * FROM \"{table}\" WHERE \"recipient\" = ? ORDER BY \"timestamp\" DESC"
+                )
+            } else {
+                format!("SELECT * FROM \"{table}\" WHERE \"recipient\" = $1 ORDER BY \"timestamp\" DESC")
+            };
+
+            export.notifications = match self
+                .base
+                .fetch_all_as::<Notification>(sqlx::query(&query).bind::<&String>(&username))
+                .await
+            {
+                Ok(n) => n,
+                Err(_) => return Err(AuthError::Other),
+            };
+        }
+
+        for log in self.get_logs_referencing(username).await? {
+            if log.logtype == AuditLog::LOGTYPE {
+                export.audit.push(log);
+            } else {
+                export.logs.push(log);
+            }
+        }
+
+        // return
+        Ok(export)
+    }
+
+    /// Erase a user and their data
+    ///
+    /// The user, their notifications, webhooks (see [`AuthDatabase::with_webhooks`]) and
+    /// external identities are deleted. Any other logs that reference the user (including
+    /// audit entries) are kept, but the username is replaced with `[deleted]` in
+    /// [`USERNAME_KEYS`].
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    pub async fn erase_user(&self, username: String) -> Result<()> {
+        let export = self.export_user_data(username.clone()).await?;
+
+        // ...
+        let sqlite_mysql = (self.base.db._type == "sqlite") | (self.base.db._type == "mysql");
+        let delete = |table: &String, column: &str| {
+            if sqlite_mysql {
+                format!("DELETE FROM \"{table}\" WHERE \"{column}\" = ?")
+            } else {
+                format!("DELETE FROM \"{table}\" WHERE \"{column}\" = $1")
+            }
+        };
+
+        let mut deletes = vec![delete(&self.options.table, "username")];
+
+        if let Some(ref table) = self.options.notifications_table {
+            deletes.push(delete(table, "recipient"));
+        }
+
+        if let Some(ref table) = self.options.identities_table {
+            deletes.push(delete(table, "username"));
+        }
+
+        #[cfg(feature = "webhooks")]
+        if let Some(ref webhooks) = self.webhooks {
+            deletes.push(delete(&webhooks.options.table, "owner"));
+        }
+
+        let anonymize = if sqlite_mysql {
+            format!(
+                "UPDATE \"{}\" SET \"content\" = ? WHERE \"id\" = ?",
+                self.options.logs_table
+            )
+        } else {
+            format!(
+                "UPDATE \"{}\" SET \"content\" = $1 WHERE \"id\" = $2",
+                self.options.logs_table
+            )
+        };
+
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(AuthError::Other),
+        };
+
+        for query in deletes {
+            if sqlx::query(&query)
+                .bind::<&String>(&username)
+                .execute(&mut *transaction)
+                .await
+                .is_err()
+            {
+                return Err(AuthError::Other);
+            }
+        }
+
+        for log in export.audit.iter().chain(export.logs.iter()) {
+            let content = match anonymize_content(&log.content, &username) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            if sqlx::query(&anonymize)
+                .bind::<&String>(&content)
+                .bind::<&String>(&log.id)
+                .execute(&mut *transaction)
+                .await
+                .is_err()
+            {
+                return Err(AuthError::Other);
+            }
+        }
+
+        if transaction.commit().await.is_err() {
+            return Err(AuthError::Other);
+        }
+
+        // update cache
+        let logs_prefix = self.logs().options.prefix;
+
+        self.base
+            .cachedb
+            .remove(format!("{}:{}", self.options.prefix, username))
+            .await;
+
+        // the cached unread count is stale once its version changes
+        self.base
+            .cachedb
+            .incr(format!(
+                "{}:unread_version:{}",
+                self.options.notifications_prefix, username
+            ))
+            .await;
+
+        for log in export.audit.iter().chain(export.logs.iter()) {
+            self.base
+                .cachedb
+                .remove(format!("{}:{}", logs_prefix, log.id))
+                .await;
+        }
+
+        // return
+        Ok(())
+    }
+}
+
+/// Keys of log contents that hold a username
+pub const USERNAME_KEYS: [&str; 3] = ["target", "actor", "user"];
+
+/// Replace `username` with `[deleted]` in the [`USERNAME_KEYS`] of a log's `content`
+///
+/// Returns none if the content doesn't reference `username`.
+///
+/// # Arguments:
+/// * `content` - content of the log (JSON object)
+/// * `username` - username of the user
+fn anonymize_content(content: &str, username: &str) -> Option<String> {
+    let mut value = match serde_json::from_str::<serde_json::Value>(content) {
+        Ok(serde_json::Value::Object(v)) => v,
+        _ => return None,
+    };
+
+    let mut referenced = false;
+
+    for key in USERNAME_KEYS {
+        if value.get(key).and_then(|v| v.as_str()) == Some(username) {
+            value.insert(key.to_string(), serde_json::Value::from("[deleted]"));
+            referenced = true;
+        }
+    }
+
+    if !referenced {
+        return None;
+    }
+
+    serde_json::to_string(&value).ok()
 }
@@ -0,0 +1,483 @@
+//! # InviteDatabase
+//! Invitation codes for gated (invite-only) registration
+use super::auth_db::UserMetadata;
+use crate::{utility, AuthDatabase, DefaultReturn, StarterDatabase};
+
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// An invitation code
+pub struct Invite {
+    pub code: String,
+    /// Username of the user who created the invite
+    pub creator: String,
+    /// Role given to users who sign up with this invite
+    pub role: String,
+    /// Maximum number of times the invite can be used (0 for unlimited)
+    pub max_uses: i64,
+    /// Number of times the invite has been used
+    pub uses: i64,
+    /// Time the invite expires at (0 for never)
+    pub expires: u128,
+    pub revoked: bool,
+    // dates
+    pub timestamp: u128,
+}
+
+impl<'r, R> sqlx::FromRow<'r, R> for Invite
+where
+    R: Row,
+    &'r str: sqlx::ColumnIndex<R>,
+    String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> sqlx::Result<Self> {
+        Ok(Invite {
+            code: row.try_get("code")?,
+            creator: row.try_get("creator")?,
+            role: row.try_get("role")?,
+            max_uses: row.try_get("max_uses")?,
+            uses: row.try_get("uses")?,
+            expires: row.try_get::<i64, _>("expires")? as u128,
+            revoked: row.try_get::<i64, _>("revoked")? == 1,
+            timestamp: row.try_get::<i64, _>("timestamp")? as u128,
+        })
+    }
+}
+
+// ...
+/// Invite database errors
+#[derive(Debug)]
+pub enum InviteError {
+    ValueError,
+    NotFound,
+    Unavailable,
+    NotAllowed,
+    Other,
+}
+
+impl InviteError {
+    pub fn to_string(&self) -> String {
+        use InviteError::*;
+        match self {
+            ValueError => String::from("One of the field values given is invalid."),
+            NotFound => String::from("No invite with this code could be found."),
+            Unavailable => String::from("This invite has expired, been revoked or been used up."),
+            NotAllowed => String::from("You are not allowed to invite users with this role."),
+            _ => String::from("An unspecified error has occured"),
+        }
+    }
+}
+
+impl<T: Default> Into<DefaultReturn<T>> for InviteError {
+    fn into(self) -> DefaultReturn<T> {
+        DefaultReturn {
+            success: false,
+            message: self.to_string(),
+            payload: T::default(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, InviteError>;
+
+// ...
+#[derive(Clone)]
+pub struct DatabaseOptions {
+    /// The table to use for database operations
+    pub table: String,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            table: String::from("Invites"),
+        }
+    }
+}
+
+// database
+#[derive(Clone)]
+pub struct InviteDatabase {
+    pub base: StarterDatabase,
+    pub auth: AuthDatabase,
+    pub options: DatabaseOptions,
+}
+
+impl InviteDatabase {
+    pub async fn new(
+        base: StarterDatabase,
+        auth: AuthDatabase,
+        options: DatabaseOptions,
+    ) -> InviteDatabase {
+        InviteDatabase {
+            base,
+            auth,
+            options,
+        }
+    }
+
+    /// Create the invites table if it doesn't already exist
+    pub async fn init(&self) -> bool {
+        let c = &self.base.db.client;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{}\" (
+                code VARCHAR(255) PRIMARY KEY,
+                creator VARCHAR(255),
+                role VARCHAR(255),
+                max_uses BIGINT,
+                uses BIGINT,
+                expires BIGINT,
+                revoked BIGINT,
+                timestamp BIGINT
+            )",
+            self.options.table
+        ))
+        .execute(c)
+        .await
+        .is_ok()
+            && self
+                .base
+                .create_index(
+                    &self.options.table,
+                    &format!("{}_creator", self.options.table),
+                    &["creator"],
+                )
+                .await
+    }
+
+    // invites
+
+    // GET
+    /// Get an [`Invite`] by its code
+    ///
+    /// # Arguments:
+    /// * `code` - `String` of the invite's code
+    pub async fn get_invite(&self, code: String) -> Result<Invite> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"code\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"code\" = $1",
+                self.options.table
+            )
+        };
+
+        match self
+            .base
+            .fetch_one_as::<Invite>(sqlx::query(&query).bind::<&String>(&code))
+            .await
+        {
+            Ok(i) => Ok(i),
+            Err(_) => Err(InviteError::NotFound),
+        }
+    }
+
+    /// Get all [`Invite`]s created by the given `creator`, newest first
+    ///
+    /// # Arguments:
+    /// * `creator` - `String` of the creator's username
+    /// * `offset` - optional pagination offset (pages are 50 invites)
+    pub async fn get_invites_by_creator(
+        &self,
+        creator: String,
+        offset: Option<i32>,
+    ) -> Result<Vec<Invite>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("SELECT * FROM \"{}\" WHERE \"creator\" = ? ORDER BY \"timestamp\" DESC LIMIT 50 OFFSET ?", self.options.table)
+        } else {
+            format!("SELECT * FROM \"{}\" WHERE \"creator\" = $1 ORDER BY \"timestamp\" DESC LIMIT 50 OFFSET $2", self.options.table)
+        };
+
+        match self
+            .base
+            .fetch_all_as::<Invite>(
+                sqlx::query(&query)
+                    .bind::<&String>(&creator)
+                    .bind(offset.unwrap_or(0)),
+            )
+            .await
+        {
+            Ok(i) => Ok(i),
+            Err(_) => Err(InviteError::Other),
+        }
+    }
+
+    /// Get the usernames of all users invited by the given `username`
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the inviting user's username
+    pub async fn get_invited_users(&self, username: String) -> Result<Vec<String>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT \"username\" FROM \"{}\" WHERE \"metadata\" LIKE ? ESCAPE '!'",
+                self.auth.options.table
+            )
+        } else {
+            format!(
+                "SELECT \"username\" FROM \"{}\" WHERE \"metadata\" LIKE $1 ESCAPE '!'",
+                self.auth.options.table
+            )
+        };
+
+        let inviter = utility::escape_like(&serde_json::to_string(&username).unwrap());
+
+        match self
+            .base
+            .fetch_all_as::<(String,)>(
+                sqlx::query(&query).bind::<String>(format!("%\"invited_by\":{inviter}%")),
+            )
+            .await
+        {
+            Ok(rows) => Ok(rows.into_iter().map(|(username,)| username).collect()),
+            Err(_) => Err(InviteError::Other),
+        }
+    }
+
+    // SET
+    /// Create a new [`Invite`]
+    ///
+    /// The invited `role` must be `member` or have a [`RoleLevel`](super::auth_db::RoleLevel),
+    /// and the creator's role must be at least as elevated as it.
+    ///
+    /// # Arguments:
+    /// * `creator` - `String` of the creator's username
+    /// * `role` - `String` of the role given to users who sign up with the invite
+    /// * `max_uses` - maximum number of uses (0 for unlimited)
+    /// * `lifetime` - how long the invite is valid for in milliseconds (none for forever)
+    pub async fn create_invite(
+        &self,
+        creator: String,
+        role: String,
+        max_uses: i64,
+        lifetime: Option<u128>,
+    ) -> Result<Invite> {
+        if (max_uses < 0) | (role == "banned") {
+            return Err(InviteError::ValueError);
+        }
+
+        // make sure creator exists
+        let elevation = match self.auth.get_user_by_username(creator.clone()).await {
+            Ok(u) => u.level.elevation,
+            Err(_) => return Err(InviteError::NotFound),
+        };
+
+        // make sure role exists and creator can give it ("member" is the default role)
+        let level = self.auth.get_level_by_role(role.clone()).await;
+
+        if (role != "member") & (level.id.is_empty() | (level.level.name != role)) {
+            return Err(InviteError::ValueError);
+        }
+
+        if level.level.elevation > elevation {
+            return Err(InviteError::NotAllowed);
+        }
+
+        // ...
+        let timestamp = utility::unix_epoch_timestamp();
+        let invite = Invite {
+            code: utility::random_id().chars().take(16).collect(),
+            creator,
+            role,
+            max_uses,
+            uses: 0,
+            expires: match lifetime {
+                Some(l) => timestamp + l,
+                None => 0,
+            },
+            revoked: false,
+            timestamp,
+        };
+
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "INSERT INTO \"{}\" VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                self.options.table
+            )
+        } else {
+            format!(
+                "INSERT INTO \"{}\" VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<&String>(&invite.code)
+            .bind::<&String>(&invite.creator)
+            .bind::<&String>(&invite.role)
+            .bind::<i64>(invite.max_uses)
+            .bind::<i64>(invite.uses)
+            .bind::<i64>(invite.expires as i64)
+            .bind::<i64>(0)
+            .bind::<i64>(invite.timestamp as i64)
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(invite),
+            Err(_) => Err(InviteError::Other),
+        }
+    }
+
+    /// Revoke an [`Invite`] given its code
+    ///
+    /// Invites can be revoked by their creator, or by users more elevated than their creator.
+    ///
+    /// # Arguments:
+    /// * `actor` - `String` of the username of the user revoking the invite
+    /// * `code` - `String` of the invite's code
+    pub async fn revoke_invite(&self, actor: String, code: String) -> Result<()> {
+        // make sure invite exists
+        let invite = self.get_invite(code.clone()).await?;
+
+        // make sure actor can revoke it
+        if actor != invite.creator {
+            let elevation = match self.auth.get_user_by_username(actor).await {
+                Ok(u) => u.level.elevation,
+                Err(_) => return Err(InviteError::NotAllowed),
+            };
+
+            // banned and erased creators count as members
+            let creator = match self.auth.get_user_by_username(invite.creator).await {
+                Ok(u) => u.level.elevation,
+                Err(_) => 0,
+            };
+
+            if elevation <= creator {
+                return Err(InviteError::NotAllowed);
+            }
+        }
+
+        // ...
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "UPDATE \"{}\" SET \"revoked\" = 1 WHERE \"code\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "UPDATE \"{}\" SET \"revoked\" = 1 WHERE \"code\" = $1",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query).bind::<&String>(&code).execute(c).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(InviteError::Other),
+        }
+    }
+
+    /// Create a new user by redeeming an invite
+    ///
+    /// The invite is redeemed and the user is created in a single transaction, so an
+    /// invite can never be used more than `max_uses` times. Returns the new user's
+    /// **unhashed** ID, which they use to login.
+    ///
+    /// # Arguments:
+    /// * `code` - `String` of the invite's code
+    /// * `username` - `String` of the new user's username
+    pub async fn create_user_with_invite(&self, code: String, username: String) -> Result<String> {
+        if AuthDatabase::validate_username(&username).is_err() {
+            return Err(InviteError::ValueError);
+        }
+
+        // ...
+        let (exists, redeem, select, insert) = if (self.base.db._type == "sqlite")
+            | (self.base.db._type == "mysql")
+        {
+            (
+                    format!("SELECT \"username\" FROM \"{}\" WHERE \"username\" = ?", self.auth.options.table),
+                    format!("UPDATE \"{}\" SET \"uses\" = \"uses\" + 1 WHERE \"code\" = ? AND \"revoked\" = 0 AND (\"max_uses\" = 0 OR \"uses\" < \"max_uses\") AND (\"expires\" = 0 OR \"expires\" > ?)", self.options.table),
+                    format!("SELECT * FROM \"{}\" WHERE \"code\" = ?", self.options.table),
+                    format!("INSERT INTO \"{}\" VALUES (?, ?, ?, ?, ?)", self.auth.options.table),
+                )
+        } else {
+            (
+                    format!("SELECT \"username\" FROM \"{}\" WHERE \"username\" = $1", self.auth.options.table),
+                    format!("UPDATE \"{}\" SET \"uses\" = \"uses\" + 1 WHERE \"code\" = $1 AND \"revoked\" = 0 AND (\"max_uses\" = 0 OR \"uses\" < \"max_uses\") AND (\"expires\" = 0 OR \"expires\" > $2)", self.options.table),
+                    format!("SELECT * FROM \"{}\" WHERE \"code\" = $1", self.options.table),
+                    format!("INSERT INTO \"{}\" VALUES ($1, $2, $3, $4, $5)", self.auth.options.table),
+                )
+        };
+
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(InviteError::Other),
+        };
+
+        // make sure user doesn't already exist (banned users included)
+        match sqlx::query(&exists)
+            .bind::<&String>(&username)
+            .fetch_optional(&mut *transaction)
+            .await
+        {
+            Ok(Some(_)) => return Err(InviteError::ValueError),
+            Ok(None) => (),
+            Err(_) => return Err(InviteError::Other),
+        };
+
+        // redeem invite
+        let timestamp = utility::unix_epoch_timestamp();
+        match sqlx::query(&redeem)
+            .bind::<&String>(&code)
+            .bind::<i64>(timestamp as i64)
+            .execute(&mut *transaction)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(InviteError::Unavailable);
+                }
+            }
+            Err(_) => return Err(InviteError::Other),
+        };
+
+        let invite = match sqlx::query_as::<_, Invite>(&select)
+            .bind::<&String>(&code)
+            .fetch_one(&mut *transaction)
+            .await
+        {
+            Ok(r) => r,
+            Err(_) => return Err(InviteError::Other),
+        };
+
+        // create user
+        let user_id: String = utility::uuid();
+        let metadata = UserMetadata {
+            invited_by: Some(invite.creator),
+            ..Default::default()
+        };
+
+        if let Err(e) = sqlx::query(&insert)
+            .bind::<&String>(&username)
+            .bind::<String>(utility::hash(user_id.clone()))
+            .bind::<&String>(&invite.role)
+            .bind::<i64>(timestamp as i64)
+            .bind::<String>(serde_json::to_string::<UserMetadata>(&metadata).unwrap())
+            .execute(&mut *transaction)
+            .await
+        {
+            // the username was taken by a concurrent signup
+            if e.as_database_error()
+                .is_some_and(|e| e.is_unique_violation())
+            {
+                return Err(InviteError::ValueError);
+            }
+
+            return Err(InviteError::Other);
+        }
+
+        if transaction.commit().await.is_err() {
+            return Err(InviteError::Other);
+        }
+
+        // return
+        Ok(user_id)
+    }
+}
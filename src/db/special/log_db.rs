@@ -1,22 +1,246 @@
 use crate::{utility, DefaultReturn, StarterDatabase};
-use serde::{Deserialize, Serialize};
+use futures_util::stream::{BoxStream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 
-#[derive(Default, PartialEq, Clone, Serialize, Deserialize)]
+#[derive(Default, PartialEq, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Log {
     // selectors
     pub id: String,
     pub logtype: String,
     // dates
+    #[sqlx(try_from = "i64")]
     pub timestamp: u128,
     // ...
     pub content: String,
 }
 
+/// A kind of log, associating a `logtype` with the type of its `content`
+///
+/// ```ignore
+/// struct PasteViewLog;
+///
+/// impl LogKind for PasteViewLog {
+///     const LOGTYPE: &'static str = "paste_view";
+///     type Payload = PasteView;
+/// }
+///
+/// logs.create_typed::<PasteViewLog>(&view).await?;
+/// ```
+pub trait LogKind {
+    /// The `logtype` logs of this kind are stored with
+    const LOGTYPE: &'static str;
+    /// The type stored (as JSON) in the log's `content`
+    type Payload: Serialize + DeserializeOwned;
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A [`Log`] with a decoded payload
+pub struct TypedLog<P> {
+    pub id: String,
+    // dates
+    pub timestamp: u128,
+    // ...
+    pub payload: P,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A page of typed logs returned by [`LogDatabase::list_typed`]
+pub struct TypedLogPage<P> {
+    pub logs: Vec<TypedLog<P>>,
+    /// Cursor for the next page, none if this is the last page
+    pub next: Option<String>,
+}
+
+impl Log {
+    /// Decode this log's content as the payload of the given [`LogKind`]
+    pub fn decode<K: LogKind>(self) -> Result<TypedLog<K::Payload>> {
+        if self.logtype != K::LOGTYPE {
+            return Err(LogError::ValueError);
+        }
+
+        match serde_json::from_str::<K::Payload>(&self.content) {
+            Ok(payload) => Ok(TypedLog {
+                id: self.id,
+                timestamp: self.timestamp,
+                payload,
+            }),
+            Err(_) => Err(LogError::ValueError),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LogIdentifier {
     pub id: String,
 }
 
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// The order logs are returned in (by timestamp)
+pub enum LogOrder {
+    /// Oldest first
+    Ascending,
+    /// Newest first
+    #[default]
+    Descending,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// A filtered, paginated query over logs
+///
+/// ```ignore
+/// let page = logs
+///     .query(LogQuery::new().logtype("notification").from(since).limit(25))
+///     .await?;
+/// ```
+pub struct LogQuery {
+    /// Only include logs of this `logtype`
+    pub logtype: Option<String>,
+    /// Only include logs created at or after this time
+    pub from: Option<u128>,
+    /// Only include logs created at or before this time
+    pub to: Option<u128>,
+    /// Only include logs whose content contains this string
+    pub content: Option<String>,
+    pub order: LogOrder,
+    /// Maximum number of logs in a page
+    pub limit: u32,
+    /// Cursor returned by a previous page ([`LogPage::next`])
+    pub cursor: Option<String>,
+}
+
+impl Default for LogQuery {
+    fn default() -> Self {
+        Self {
+            logtype: None,
+            from: None,
+            to: None,
+            content: None,
+            order: LogOrder::default(),
+            limit: 50,
+            cursor: None,
+        }
+    }
+}
+
+impl LogQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn logtype(mut self, logtype: &str) -> Self {
+        self.logtype = Some(logtype.to_string());
+        self
+    }
+
+    pub fn from(mut self, timestamp: u128) -> Self {
+        self.from = Some(timestamp);
+        self
+    }
+
+    pub fn to(mut self, timestamp: u128) -> Self {
+        self.to = Some(timestamp);
+        self
+    }
+
+    pub fn content(mut self, content: &str) -> Self {
+        self.content = Some(content.to_string());
+        self
+    }
+
+    pub fn order(mut self, order: LogOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn cursor(mut self, cursor: Option<String>) -> Self {
+        self.cursor = cursor;
+        self
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// Which new logs are sent to a [`LogDatabase::subscribe`] stream
+pub struct LogFilter {
+    /// Only include logs of this `logtype`
+    pub logtype: Option<String>,
+    /// Only include logs whose content contains this string
+    pub content: Option<String>,
+}
+
+impl LogFilter {
+    /// Check if the given [`Log`] matches this filter
+    pub fn matches(&self, log: &Log) -> bool {
+        if let Some(ref logtype) = self.logtype {
+            if &log.logtype != logtype {
+                return false;
+            }
+        }
+
+        if let Some(ref content) = self.content {
+            if !log.content.contains(content.as_str()) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A page of logs returned by [`LogDatabase::query`]
+pub struct LogPage {
+    pub logs: Vec<Log>,
+    /// Cursor for the next page, none if this is the last page
+    pub next: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// How long logs of a `logtype` are kept for
+pub struct RetentionPolicy {
+    pub logtype: String,
+    /// Logs older than this (in milliseconds) are removed
+    pub max_age: Option<u128>,
+    /// Only the newest `max_count` logs are kept
+    pub max_count: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// The result of [`LogDatabase::prune`]
+pub struct PruneReport {
+    /// Total number of logs removed
+    pub removed: u64,
+    /// Number of logs removed for each `logtype`
+    pub by_logtype: HashMap<String, u64>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// What [`LogDatabase::import`] does with logs that already exist
+pub enum ImportMode {
+    /// Keep the existing log
+    #[default]
+    Skip,
+    /// Replace the existing log with the imported one
+    Overwrite,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// The result of [`LogDatabase::import`]
+pub struct ImportReport {
+    /// Number of new logs created
+    pub created: u64,
+    /// Number of existing logs replaced
+    pub overwritten: u64,
+    /// Number of existing logs kept
+    pub skipped: u64,
+}
+
 // ...
 /// Log database errors
 #[derive(Debug)]
@@ -56,6 +280,21 @@ pub struct DatabaseOptions {
     pub table: String,
     /// The prefix used in redis keys
     pub prefix: String,
+    /// Retention rules applied by [`LogDatabase::prune`]
+    pub retention: Vec<RetentionPolicy>,
+    /// Maximum number of logs deleted by a single prune query
+    pub prune_batch_size: u32,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            table: String::from("Logs"),
+            prefix: String::from("log"),
+            retention: Vec::new(),
+            prune_batch_size: 500,
+        }
+    }
 }
 
 // database
@@ -70,6 +309,61 @@ impl LogDatabase {
         LogDatabase { base, options }
     }
 
+    /// Create the logs table (and its indexes) if it doesn't already exist
+    pub async fn init(&self) -> bool {
+        let c = &self.base.db.client;
+        let table = &self.options.table;
+
+        if sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{table}\" (
+                id VARCHAR(255),
+                logtype VARCHAR(255),
+                timestamp BIGINT,
+                content TEXT
+            )"
+        ))
+        .execute(c)
+        .await
+        .is_err()
+        {
+            return false;
+        }
+
+        self.base
+            .create_index(table, &format!("{table}_id"), &["id"])
+            .await
+            && self
+                .base
+                .create_index(
+                    table,
+                    &format!("{table}_logtype"),
+                    &["logtype", "timestamp"],
+                )
+                .await
+    }
+
+    /// Convert a logs table created before timestamps were stored as `BIGINT`
+    ///
+    /// On sqlite the search index must be created again afterwards.
+    pub async fn migrate(&self) -> bool {
+        let table = &self.options.table;
+
+        self.base
+            .migrate_timestamp_column(
+                table,
+                &format!(
+                    "CREATE TABLE \"{table}\" (
+                        id VARCHAR(255),
+                        logtype VARCHAR(255),
+                        timestamp BIGINT,
+                        content TEXT
+                    )"
+                ),
+            )
+            .await
+            && self.init().await
+    }
+
     // logs
 
     // GET
@@ -100,19 +394,16 @@ impl LogDatabase {
             format!("SELECT * FROM \"{}\" WHERE \"id\" = $1", self.options.table)
         };
 
-        let c = &self.base.db.client;
-        let row = match sqlx::query(&query).bind::<&String>(&id).fetch_one(c).await {
-            Ok(r) => self.base.textify_row(r).data,
+        let log = match self
+            .base
+            .fetch_one_as::<Log>(sqlx::query(&query).bind::<&String>(&id))
+            .await
+        {
+            Ok(l) => l,
             Err(_) => return Err(LogError::Other),
         };
 
         // store in cache
-        let log = Log {
-            id: row.get("id").unwrap().to_string(),
-            logtype: row.get("logtype").unwrap().to_string(),
-            timestamp: row.get("timestamp").unwrap().parse::<u128>().unwrap(),
-            content: row.get("content").unwrap().to_string(),
-        };
 
         self.base
             .cachedb
@@ -129,10 +420,12 @@ impl LogDatabase {
     // SET
     /// Create a log given its type and content
     ///
+    /// Returns the created [`Log`].
+    ///
     /// # Arguments:
     /// * `logtype` - `String` of the log's `logtype`
     /// * `content` - `String` of the log's `content`
-    pub async fn create_log(&self, logtype: String, content: String) -> Result<()> {
+    pub async fn create_log(&self, logtype: String, content: String) -> Result<Log> {
         let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
             format!("INSERT INTO \"{}\" VALUES (?, ?, ?, ?)", self.options.table)
         } else {
@@ -142,22 +435,133 @@ impl LogDatabase {
             )
         };
 
-        let log_id: String = utility::random_id();
+        let log = Log {
+            id: utility::random_id(),
+            logtype,
+            timestamp: utility::unix_epoch_timestamp(),
+            content,
+        };
 
         let c = &self.base.db.client;
         match sqlx::query(&query)
-            .bind::<&String>(&log_id)
-            .bind::<String>(logtype)
-            .bind::<String>(utility::unix_epoch_timestamp().to_string())
-            .bind::<String>(content)
+            .bind::<&String>(&log.id)
+            .bind::<&String>(&log.logtype)
+            .bind::<i64>(log.timestamp as i64)
+            .bind::<&String>(&log.content)
             .execute(c)
             .await
         {
-            Ok(_) => return Ok(()),
+            Ok(_) => {
+                self.publish_log(&log).await;
+                return Ok(log);
+            }
             Err(_) => return Err(LogError::Other),
         };
     }
 
+    /// Create many logs in a single transaction given their types and contents
+    ///
+    /// Either every log is created or none are. Returns the created [`Log`]s.
+    ///
+    /// # Arguments:
+    /// * `logs` - `Vec` of each log's `logtype` and `content`
+    pub async fn create_logs(&self, logs: Vec<(String, String)>) -> Result<Vec<Log>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("INSERT INTO \"{}\" VALUES (?, ?, ?, ?)", self.options.table)
+        } else {
+            format!(
+                "INSERT INTO \"{}\" VALUES ($1, $2, $3, $4)",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(LogError::Other),
+        };
+
+        let timestamp = utility::unix_epoch_timestamp();
+        let mut output: Vec<Log> = Vec::new();
+
+        for (logtype, content) in logs {
+            let log = Log {
+                id: utility::random_id(),
+                logtype,
+                timestamp,
+                content,
+            };
+
+            if sqlx::query(&query)
+                .bind::<&String>(&log.id)
+                .bind::<&String>(&log.logtype)
+                .bind::<i64>(log.timestamp as i64)
+                .bind::<&String>(&log.content)
+                .execute(&mut *transaction)
+                .await
+                .is_err()
+            {
+                return Err(LogError::Other);
+            }
+
+            output.push(log);
+        }
+
+        if transaction.commit().await.is_err() {
+            return Err(LogError::Other);
+        }
+
+        for log in &output {
+            self.publish_log(log).await;
+        }
+
+        // return
+        Ok(output)
+    }
+
+    /// Publish a newly created [`Log`] to [`LogDatabase::subscribe`] streams
+    ///
+    /// # Arguments:
+    /// * `log` - the created [`Log`]
+    async fn publish_log(&self, log: &Log) {
+        self.base
+            .pubsub
+            .publish(
+                format!("{}:new", self.options.prefix),
+                serde_json::to_string::<Log>(log).unwrap(),
+            )
+            .await;
+    }
+
+    /// Subscribe to new logs matching the given [`LogFilter`]
+    ///
+    /// Every log created through [`LogDatabase::create_log`] or
+    /// [`LogDatabase::create_logs`] after subscribing is sent to the stream.
+    ///
+    /// # Arguments:
+    /// * `filter` - [`LogFilter`]
+    pub async fn subscribe(&self, filter: LogFilter) -> Result<BoxStream<'static, Log>> {
+        let stream = match self
+            .base
+            .pubsub
+            .subscribe(format!("{}:new", self.options.prefix))
+            .await
+        {
+            Some(s) => s,
+            None => return Err(LogError::Other),
+        };
+
+        Ok(stream
+            .filter_map(move |message| {
+                let log = serde_json::from_str::<Log>(&message)
+                    .ok()
+                    .filter(|l| filter.matches(l));
+
+                async move { log }
+            })
+            .boxed())
+    }
+
     /// Edit a log given its ID
     ///
     /// # Arguments:
@@ -235,4 +639,633 @@ impl LogDatabase {
             Err(_) => return Err(LogError::Other),
         }
     }
+
+    /// Get a page of logs matching the given [`LogQuery`]
+    ///
+    /// # Arguments:
+    /// * `props` - [`LogQuery`]
+    pub async fn query(&self, props: LogQuery) -> Result<LogPage> {
+        let is_postgres = self.base.db._type == "postgres";
+
+        let mut conditions: Vec<String> = Vec::new();
+        let mut binds: Vec<String> = Vec::new();
+        let mut param = |value: String| -> String {
+            binds.push(value);
+
+            if is_postgres {
+                format!("${}", binds.len())
+            } else {
+                String::from("?")
+            }
+        };
+
+        // build conditions
+        if let Some(logtype) = props.logtype {
+            conditions.push(format!("\"logtype\" = {}", param(logtype)));
+        }
+
+        // timestamps are numbers, so they're safe to include directly
+        if let Some(from) = props.from {
+            conditions.push(format!("\"timestamp\" >= {}", from as i64));
+        }
+
+        if let Some(to) = props.to {
+            conditions.push(format!("\"timestamp\" <= {}", to as i64));
+        }
+
+        if let Some(content) = props.content {
+            let escaped = utility::escape_like(&content);
+
+            conditions.push(format!(
+                "\"content\" LIKE {} ESCAPE '!'",
+                param(format!("%{escaped}%"))
+            ));
+        }
+
+        let (op, direction) = match props.order {
+            LogOrder::Ascending => (">", "ASC"),
+            LogOrder::Descending => ("<", "DESC"),
+        };
+
+        if let Some(cursor) = props.cursor {
+            let (timestamp, id) = match cursor.split_once(':') {
+                Some((t, i)) => match t.parse::<i64>() {
+                    Ok(t) => (t, i.to_string()),
+                    Err(_) => return Err(LogError::ValueError),
+                },
+                None => return Err(LogError::ValueError),
+            };
+
+            conditions.push(format!(
+                "(\"timestamp\" {op} {timestamp} OR (\"timestamp\" = {timestamp} AND \"id\" {op} {}))",
+                param(id)
+            ));
+        }
+
+        // ...
+        let query: String = format!(
+            "SELECT * FROM \"{}\"{} ORDER BY \"timestamp\" {direction}, \"id\" {direction} LIMIT {}",
+            self.options.table,
+            if conditions.is_empty() {
+                String::new()
+            } else {
+                format!(" WHERE {}", conditions.join(" AND "))
+            },
+            // fetch one extra log to know if there is a next page
+            props.limit as u64 + 1
+        );
+
+        let mut q = sqlx::query(&query);
+
+        for bind in &binds {
+            q = q.bind::<&String>(bind);
+        }
+
+        let mut logs = match self.base.fetch_all_as::<Log>(q).await {
+            Ok(l) => l,
+            Err(_) => return Err(LogError::Other),
+        };
+
+        let next = if logs.len() > props.limit as usize {
+            logs.truncate(props.limit as usize);
+            logs.last().map(|l| format!("{}:{}", l.timestamp, l.id))
+        } else {
+            None
+        };
+
+        // return
+        Ok(LogPage { logs, next })
+    }
+
+    // typed
+
+    /// Create a log of the given [`LogKind`]
+    ///
+    /// # Arguments:
+    /// * `payload` - the log's payload
+    pub async fn create_typed<K: LogKind>(
+        &self,
+        payload: &K::Payload,
+    ) -> Result<TypedLog<K::Payload>> {
+        let content = match serde_json::to_string(payload) {
+            Ok(c) => c,
+            Err(_) => return Err(LogError::ValueError),
+        };
+
+        self.create_log(K::LOGTYPE.to_string(), content)
+            .await?
+            .decode::<K>()
+    }
+
+    /// Get a log of the given [`LogKind`] by its id
+    ///
+    /// # Arguments:
+    /// * `id` - `String` of the log's `id`
+    pub async fn get_typed<K: LogKind>(&self, id: String) -> Result<TypedLog<K::Payload>> {
+        self.get_log_by_id(id).await?.decode::<K>()
+    }
+
+    /// Get a page of logs of the given [`LogKind`] (the query's `logtype` is ignored)
+    ///
+    /// # Arguments:
+    /// * `props` - [`LogQuery`]
+    pub async fn list_typed<K: LogKind>(
+        &self,
+        props: LogQuery,
+    ) -> Result<TypedLogPage<K::Payload>> {
+        let page = self.query(props.logtype(K::LOGTYPE)).await?;
+
+        // decode
+        let mut logs: Vec<TypedLog<K::Payload>> = Vec::new();
+
+        for log in page.logs {
+            logs.push(log.decode::<K>()?);
+        }
+
+        // return
+        Ok(TypedLogPage {
+            logs,
+            next: page.next,
+        })
+    }
+
+    // retention
+
+    /// Delete the logs with the given ids, returning how many were deleted
+    ///
+    /// # Arguments:
+    /// * `ids` - ids of the logs to delete
+    async fn delete_logs(&self, ids: Vec<String>) -> Result<u64> {
+        let params: Vec<String> = (1..=ids.len())
+            .map(|i| {
+                if self.base.db._type == "postgres" {
+                    format!("${i}")
+                } else {
+                    String::from("?")
+                }
+            })
+            .collect();
+
+        let query = format!(
+            "DELETE FROM \"{}\" WHERE \"id\" IN ({})",
+            self.options.table,
+            params.join(", ")
+        );
+
+        let mut q = sqlx::query(&query);
+
+        for id in &ids {
+            q = q.bind::<&String>(id);
+        }
+
+        let c = &self.base.db.client;
+        let removed = match q.execute(c).await {
+            Ok(r) => r.rows_affected(),
+            Err(_) => return Err(LogError::Other),
+        };
+
+        // update cache
+        for id in ids {
+            self.base
+                .cachedb
+                .remove(format!("{}:{}", self.options.prefix, id))
+                .await;
+        }
+
+        // return
+        Ok(removed)
+    }
+
+    /// Remove logs that are no longer kept by [`DatabaseOptions::retention`]
+    ///
+    /// Logs are deleted in batches of [`DatabaseOptions::prune_batch_size`].
+    pub async fn prune(&self) -> Result<PruneReport> {
+        let mut report = PruneReport::default();
+        let batch = self.options.prune_batch_size.max(1);
+
+        for policy in &self.options.retention {
+            let mut removed: u64 = 0;
+
+            // max age
+            if let Some(max_age) = policy.max_age {
+                let cutoff = utility::unix_epoch_timestamp().saturating_sub(max_age);
+                let query: String = if (self.base.db._type == "sqlite")
+                    | (self.base.db._type == "mysql")
+                {
+                    format!("SELECT \"id\" FROM \"{}\" WHERE \"logtype\" = ? AND \"timestamp\" < ? ORDER BY \"timestamp\" ASC LIMIT {batch}", self.options.table)
+                } else {
+                    format!("SELECT \"id\" FROM \"{}\" WHERE \"logtype\" = $1 AND \"timestamp\" < $2 ORDER BY \"timestamp\" ASC LIMIT {batch}", self.options.table)
+                };
+
+                loop {
+                    let ids: Vec<String> = match self
+                        .base
+                        .fetch_all_as::<(String,)>(
+                            sqlx::query(&query)
+                                .bind::<&String>(&policy.logtype)
+                                .bind::<i64>(cutoff as i64),
+                        )
+                        .await
+                    {
+                        Ok(r) => r.into_iter().map(|(id,)| id).collect(),
+                        Err(_) => return Err(LogError::Other),
+                    };
+
+                    if ids.is_empty() {
+                        break;
+                    }
+
+                    removed += self.delete_logs(ids).await?;
+                }
+            }
+
+            // max count
+            if let Some(max_count) = policy.max_count {
+                let query: String = if (self.base.db._type == "sqlite")
+                    | (self.base.db._type == "mysql")
+                {
+                    format!("SELECT \"id\" FROM \"{}\" WHERE \"logtype\" = ? ORDER BY \"timestamp\" DESC, \"id\" DESC LIMIT {batch} OFFSET {max_count}", self.options.table)
+                } else {
+                    format!("SELECT \"id\" FROM \"{}\" WHERE \"logtype\" = $1 ORDER BY \"timestamp\" DESC, \"id\" DESC LIMIT {batch} OFFSET {max_count}", self.options.table)
+                };
+
+                loop {
+                    let ids: Vec<String> = match self
+                        .base
+                        .fetch_all_as::<(String,)>(
+                            sqlx::query(&query).bind::<&String>(&policy.logtype),
+                        )
+                        .await
+                    {
+                        Ok(r) => r.into_iter().map(|(id,)| id).collect(),
+                        Err(_) => return Err(LogError::Other),
+                    };
+
+                    if ids.is_empty() {
+                        break;
+                    }
+
+                    removed += self.delete_logs(ids).await?;
+                }
+            }
+
+            report.removed += removed;
+            *report.by_logtype.entry(policy.logtype.clone()).or_insert(0) += removed;
+        }
+
+        // return
+        Ok(report)
+    }
+
+    /// Spawn a task on the current tokio runtime that calls [`LogDatabase::prune`]
+    /// every `interval`
+    ///
+    /// # Arguments:
+    /// * `interval` - time between prunes
+    pub fn spawn_pruner(&self, interval: std::time::Duration) -> tokio::task::JoinHandle<()> {
+        let db = self.clone();
+
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+
+            loop {
+                timer.tick().await;
+                let _ = db.prune().await;
+            }
+        })
+    }
+
+    // archive
+
+    /// Write every log matching the given [`LogQuery`] to `writer` as newline-delimited JSON
+    ///
+    /// Returns the number of logs written.
+    ///
+    /// # Arguments:
+    /// * `props` - [`LogQuery`] (its `limit` is used as the page size)
+    /// * `writer` - where logs are written to
+    pub async fn export<W: AsyncWrite + Unpin>(
+        &self,
+        props: LogQuery,
+        writer: &mut W,
+    ) -> Result<u64> {
+        let mut props = props;
+        let mut written: u64 = 0;
+
+        loop {
+            let page = self.query(props.clone()).await?;
+
+            for log in &page.logs {
+                let mut line = serde_json::to_string::<Log>(log).unwrap();
+                line.push('\n');
+
+                if writer.write_all(line.as_bytes()).await.is_err() {
+                    return Err(LogError::Other);
+                }
+
+                written += 1;
+            }
+
+            if page.next.is_none() {
+                break;
+            }
+
+            props.cursor = page.next;
+        }
+
+        if writer.flush().await.is_err() {
+            return Err(LogError::Other);
+        }
+
+        // return
+        Ok(written)
+    }
+
+    /// Read newline-delimited JSON logs (as written by [`LogDatabase::export`]) from `reader`
+    ///
+    /// Log ids and timestamps are preserved. Logs are imported in a single transaction,
+    /// so a malformed line imports nothing.
+    ///
+    /// # Arguments:
+    /// * `reader` - where logs are read from
+    /// * `mode` - [`ImportMode`] for logs that already exist
+    pub async fn import<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        mode: ImportMode,
+    ) -> Result<ImportReport> {
+        let (exists, insert, update) = if (self.base.db._type == "sqlite")
+            | (self.base.db._type == "mysql")
+        {
+            (
+                    format!("SELECT \"id\" FROM \"{}\" WHERE \"id\" = ?", self.options.table),
+                    format!("INSERT INTO \"{}\" VALUES (?, ?, ?, ?)", self.options.table),
+                    format!(
+                        "UPDATE \"{}\" SET \"logtype\" = ?, \"timestamp\" = ?, \"content\" = ? WHERE \"id\" = ?",
+                        self.options.table
+                    ),
+                )
+        } else {
+            (
+                    format!("SELECT \"id\" FROM \"{}\" WHERE \"id\" = $1", self.options.table),
+                    format!("INSERT INTO \"{}\" VALUES ($1, $2, $3, $4)", self.options.table),
+                    format!(
+                        "UPDATE \"{}\" SET \"logtype\" = $1, \"timestamp\" = $2, \"content\" = $3 WHERE \"id\" = $4",
+                        self.options.table
+                    ),
+                )
+        };
+
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(LogError::Other),
+        };
+
+        let mut report = ImportReport::default();
+        let mut overwritten: Vec<String> = Vec::new();
+        let mut lines = BufReader::new(reader).lines();
+
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(l)) => l,
+                Ok(None) => break,
+                Err(_) => return Err(LogError::Other),
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let log = match serde_json::from_str::<Log>(&line) {
+                Ok(l) => l,
+                Err(_) => return Err(LogError::ValueError),
+            };
+
+            // check if log exists
+            let existing = match sqlx::query(&exists)
+                .bind::<&String>(&log.id)
+                .fetch_optional(&mut *transaction)
+                .await
+            {
+                Ok(r) => r.is_some(),
+                Err(_) => return Err(LogError::Other),
+            };
+
+            if existing && (mode == ImportMode::Skip) {
+                report.skipped += 1;
+                continue;
+            }
+
+            // write log
+            let res = if existing {
+                sqlx::query(&update)
+                    .bind::<&String>(&log.logtype)
+                    .bind::<i64>(log.timestamp as i64)
+                    .bind::<&String>(&log.content)
+                    .bind::<&String>(&log.id)
+                    .execute(&mut *transaction)
+                    .await
+            } else {
+                sqlx::query(&insert)
+                    .bind::<&String>(&log.id)
+                    .bind::<&String>(&log.logtype)
+                    .bind::<i64>(log.timestamp as i64)
+                    .bind::<&String>(&log.content)
+                    .execute(&mut *transaction)
+                    .await
+            };
+
+            if res.is_err() {
+                return Err(LogError::Other);
+            }
+
+            if existing {
+                report.overwritten += 1;
+                overwritten.push(log.id);
+            } else {
+                report.created += 1;
+            }
+        }
+
+        if transaction.commit().await.is_err() {
+            return Err(LogError::Other);
+        }
+
+        // update cache
+        for id in overwritten {
+            self.base
+                .cachedb
+                .remove(format!("{}:{}", self.options.prefix, id))
+                .await;
+        }
+
+        // return
+        Ok(report)
+    }
+
+    // search
+
+    /// Create the full-text search index used by [`LogDatabase::search`]
+    ///
+    /// Uses FTS5 (kept up to date with triggers) on sqlite, a GIN `tsvector` index on
+    /// postgres and a `FULLTEXT` index on mysql. Safe to call more than once.
+    ///
+    /// The logs table has no `INTEGER PRIMARY KEY`, so its rowids can change (ex: `VACUUM`).
+    /// The FTS5 index is keyed on the rowids of `{table}_fts_ids` instead, which maps them
+    /// to log ids. Log ids are made unique first, which fails if the table already has
+    /// duplicate ids.
+    pub async fn create_search_index(&self) -> Result<()> {
+        let table = &self.options.table;
+        let index = format!("{table}_fts");
+
+        let queries: Vec<String> = if self.base.db._type == "sqlite" {
+            vec![
+                // the index maps log ids, so they must be unique
+                format!("CREATE UNIQUE INDEX IF NOT EXISTS \"{index}_log_ids\" ON \"{table}\" (\"id\")"),
+                format!("CREATE TABLE IF NOT EXISTS \"{index}_ids\" (docid INTEGER PRIMARY KEY, id VARCHAR(255) UNIQUE)"),
+                format!("CREATE VIRTUAL TABLE IF NOT EXISTS \"{index}\" USING fts5(content, content='')"),
+                // a log is indexed when its id is mapped
+                format!("CREATE TRIGGER IF NOT EXISTS \"{index}_ids_insert\" AFTER INSERT ON \"{index}_ids\" BEGIN INSERT INTO \"{index}\"(rowid, content) SELECT new.docid, \"content\" FROM \"{table}\" WHERE \"id\" = new.id; END"),
+                format!("CREATE TRIGGER IF NOT EXISTS \"{index}_insert\" AFTER INSERT ON \"{table}\" BEGIN INSERT INTO \"{index}_ids\"(id) VALUES (new.id); END"),
+                format!("CREATE TRIGGER IF NOT EXISTS \"{index}_delete\" AFTER DELETE ON \"{table}\" BEGIN INSERT INTO \"{index}\"(\"{index}\", rowid, content) SELECT 'delete', docid, old.content FROM \"{index}_ids\" WHERE id = old.id; DELETE FROM \"{index}_ids\" WHERE id = old.id; END"),
+                format!("CREATE TRIGGER IF NOT EXISTS \"{index}_update\" AFTER UPDATE ON \"{table}\" BEGIN INSERT INTO \"{index}\"(\"{index}\", rowid, content) SELECT 'delete', docid, old.content FROM \"{index}_ids\" WHERE id = old.id; UPDATE \"{index}_ids\" SET id = new.id WHERE id = old.id; INSERT INTO \"{index}\"(rowid, content) SELECT docid, new.content FROM \"{index}_ids\" WHERE id = new.id; END"),
+                // index logs created before the index
+                format!("INSERT INTO \"{index}_ids\"(id) SELECT \"id\" FROM \"{table}\" WHERE \"id\" NOT IN (SELECT id FROM \"{index}_ids\")"),
+            ]
+        } else if self.base.db._type == "postgres" {
+            vec![format!(
+                "CREATE INDEX IF NOT EXISTS \"{index}\" ON \"{table}\" USING GIN (to_tsvector('simple', \"content\"))"
+            )]
+        } else {
+            // mysql doesn't support "IF NOT EXISTS" for indexes
+            let c = &self.base.db.client;
+            let exists = match sqlx::query("SELECT COUNT(*) AS \"count\" FROM information_schema.statistics WHERE table_schema = DATABASE() AND table_name = ? AND index_name = ?")
+                .bind::<&String>(table)
+                .bind::<&String>(&index)
+                .fetch_one(c)
+                .await
+            {
+                Ok(r) => sqlx::Row::try_get::<i64, _>(&r, "count").unwrap_or(0) > 0,
+                Err(_) => return Err(LogError::Other),
+            };
+
+            if exists {
+                return Ok(());
+            }
+
+            vec![format!(
+                "ALTER TABLE \"{table}\" ADD FULLTEXT INDEX \"{index}\" (\"content\")"
+            )]
+        };
+
+        let c = &self.base.db.client;
+        for query in queries {
+            if sqlx::query(&query).execute(c).await.is_err() {
+                return Err(LogError::Other);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Search log content using the full-text search index, most relevant first
+    ///
+    /// Requires [`LogDatabase::create_search_index`]. Every word in `query` must match.
+    ///
+    /// # Arguments:
+    /// * `query` - `String` of the words to search for
+    /// * `logtype` - only include logs of this `logtype`
+    /// * `limit` - maximum number of logs returned
+    pub async fn search(
+        &self,
+        query: String,
+        logtype: Option<String>,
+        limit: u32,
+    ) -> Result<Vec<Log>> {
+        let table = &self.options.table;
+        let index = format!("{table}_fts");
+
+        let (sql, binds): (String, Vec<String>) = if self.base.db._type == "sqlite" {
+            // quote every word so user input is never parsed as fts5 query syntax
+            let terms: Vec<String> = query
+                .split_whitespace()
+                .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+                .collect();
+
+            if terms.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let mut binds = vec![terms.join(" ")];
+            let filter = match logtype {
+                Some(l) => {
+                    binds.push(l);
+                    " AND \"l\".\"logtype\" = ?"
+                }
+                None => "",
+            };
+
+            (
+                format!("SELECT \"l\".* FROM \"{index}\" JOIN \"{index}_ids\" AS \"i\" ON \"i\".docid = \"{index}\".rowid JOIN \"{table}\" AS \"l\" ON \"l\".\"id\" = \"i\".id WHERE \"{index}\" MATCH ?{filter} ORDER BY \"{index}\".rank LIMIT {limit}"),
+                binds,
+            )
+        } else if self.base.db._type == "postgres" {
+            let mut binds = vec![query];
+            let filter = match logtype {
+                Some(l) => {
+                    binds.push(l);
+                    " AND \"logtype\" = $2"
+                }
+                None => "",
+            };
+
+            (
+                format!("SELECT * FROM \"{table}\" WHERE to_tsvector('simple', \"content\") @@ plainto_tsquery('simple', $1){filter} ORDER BY ts_rank(to_tsvector('simple', \"content\"), plainto_tsquery('simple', $1)) DESC LIMIT {limit}"),
+                binds,
+            )
+        } else {
+            // require every word, and quote them so user input is never parsed as operators
+            let terms: Vec<String> = query
+                .split_whitespace()
+                .map(|t| t.replace('"', ""))
+                .filter(|t| !t.is_empty())
+                .map(|t| format!("+\"{t}\""))
+                .collect();
+
+            if terms.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let terms = terms.join(" ");
+            let mut binds = vec![terms.clone()];
+            let filter = match logtype {
+                Some(l) => {
+                    binds.push(l);
+                    " AND \"logtype\" = ?"
+                }
+                None => "",
+            };
+            binds.push(terms);
+
+            (
+                format!("SELECT * FROM \"{table}\" WHERE MATCH(\"content\") AGAINST(? IN BOOLEAN MODE){filter} ORDER BY MATCH(\"content\") AGAINST(? IN BOOLEAN MODE) DESC LIMIT {limit}"),
+                binds,
+            )
+        };
+
+        let mut q = sqlx::query(&sql);
+
+        for bind in &binds {
+            q = q.bind::<&String>(bind);
+        }
+
+        let output = match self.base.fetch_all_as::<Log>(q).await {
+            Ok(l) => l,
+            Err(_) => return Err(LogError::Other),
+        };
+
+        // return
+        Ok(output)
+    }
 }
@@ -1,3 +1,10 @@
 pub mod auth_db;
+pub mod invite_db;
 pub mod log_db;
 pub mod notification_db;
+
+#[cfg(feature = "oauth")]
+pub mod oauth_db;
+
+#[cfg(feature = "webhooks")]
+pub mod webhook_db;
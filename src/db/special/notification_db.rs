@@ -1,13 +1,273 @@
-use super::log_db::{Log, LogError, Result as LogResult};
-use crate::{AuthDatabase, DefaultReturn, LogDatabase, StarterDatabase};
+use super::auth_db::{UserMetadata, UserState};
+use super::log_db::{Log, LogKind};
+#[cfg(feature = "webhooks")]
+use super::webhook_db::{service_owner, WebhookDatabase};
+use crate::mail::{Email, Mailer};
+use crate::{utility, AuthDatabase, DefaultReturn, LogDatabase, StarterDatabase};
+use futures_util::stream::{self, BoxStream, StreamExt};
 use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// How long a cached unread count is kept (in seconds)
+const UNREAD_COUNT_TTL: u64 = 60 * 60;
+
+/// How many times a grouped notification is stored again after a conflict
+const GROUPED_RETRIES: u32 = 5;
+
+/// Check if a query failed because of a concurrent transaction (a unique violation,
+/// deadlock or serialization failure), so it can be retried
+///
+/// # Arguments:
+/// * `error` - the query's error
+fn is_conflict(error: &sqlx::Error) -> bool {
+    match error.as_database_error() {
+        Some(e) => {
+            // serialization failures (and mysql deadlocks) are "40001", postgres deadlocks
+            // are "40P01", and "1213" is the mysql deadlock error number
+            e.is_unique_violation()
+                | e.code()
+                    .is_some_and(|c| matches!(c.as_ref(), "40001" | "40P01" | "1213"))
+        }
+        None => false,
+    }
+}
 
 #[derive(Default, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Notification {
+    /// Unique id of the notification (set when the notification is created)
+    #[serde(default)]
+    pub id: String,
     pub service: String, // identifier for the service the notification is from
     pub user: String,    // the user that is being notified
     pub content: String, // notification text
     pub address: String, // notification redirect url
+    /// Event type of the notification, its content is rendered from the matching
+    /// [`NotificationTemplate`] (if there is one)
+    #[serde(default)]
+    pub event: Option<String>,
+    /// Named parameters of the notification's template
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    // grouping
+    /// Group of the notification, repeated notifications of the same group are
+    /// collapsed into a single notification (see [`DatabaseOptions::group_window`])
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Number of notifications collapsed into this notification
+    #[serde(default)]
+    pub count: u32,
+    /// Users who caused the notification, latest first
+    #[serde(default)]
+    pub actors: Vec<String>,
+    // dates
+    /// Time the notification was created (set when the notification is created)
+    #[serde(default)]
+    pub timestamp: u128,
+    /// Time the notification was read, none if it is unread
+    pub read_at: Option<u128>,
+}
+
+impl<'r, R> sqlx::FromRow<'r, R> for Notification
+where
+    R: Row,
+    &'r str: sqlx::ColumnIndex<R>,
+    String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> sqlx::Result<Self> {
+        Ok(Notification {
+            id: row.try_get("id")?,
+            service: row.try_get("service")?,
+            user: row.try_get("recipient")?,
+            content: row.try_get("content")?,
+            address: row.try_get("address")?,
+            event: row.try_get("event")?,
+            params: match row.try_get::<Option<String>, _>("params")? {
+                Some(p) => serde_json::from_str(&p).unwrap_or_default(),
+                None => BTreeMap::new(),
+            },
+            group: row.try_get("group_key")?,
+            count: row.try_get::<Option<i64>, _>("count")?.unwrap_or(1) as u32,
+            actors: match row.try_get::<Option<String>, _>("actors")? {
+                Some(a) => serde_json::from_str(&a).unwrap_or_default(),
+                None => Vec::new(),
+            },
+            timestamp: row.try_get::<i64, _>("timestamp")? as u128,
+            read_at: row.try_get::<Option<i64>, _>("read_at")?.map(|t| t as u128),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// Localized text of a [`Notification`], see [`DatabaseOptions::templates`]
+pub struct NotificationTemplate {
+    /// Service the template belongs to
+    pub service: String,
+    /// Event type the template is used for
+    pub event: String,
+    /// Text of the template for each locale, with parameters written as `{name}`
+    pub locales: HashMap<String, String>,
+}
+
+impl NotificationTemplate {
+    /// Create a new [`NotificationTemplate`] without any locales
+    ///
+    /// # Arguments:
+    /// * `service` - service the template belongs to
+    /// * `event` - event type the template is used for
+    pub fn new(service: &str, event: &str) -> Self {
+        Self {
+            service: service.to_string(),
+            event: event.to_string(),
+            locales: HashMap::new(),
+        }
+    }
+
+    /// Add the text of a locale
+    ///
+    /// # Arguments:
+    /// * `locale` - locale of the text (e.g. "en" or "pt-BR")
+    /// * `text` - template text, with parameters written as `{name}`
+    pub fn with_locale(mut self, locale: &str, text: &str) -> Self {
+        self.locales.insert(locale.to_string(), text.to_string());
+        self
+    }
+
+    /// Render the template in the given locale
+    ///
+    /// Regional locales fall back to their language ("pt-BR" to "pt"), then to `fallback`.
+    /// Parameters that aren't given are left as they are.
+    ///
+    /// # Arguments:
+    /// * `locale` - preferred locale
+    /// * `fallback` - locale used if the template doesn't have the preferred locale
+    /// * `params` - values of the template's parameters
+    pub fn render(
+        &self,
+        locale: &str,
+        fallback: &str,
+        params: &BTreeMap<String, String>,
+    ) -> Option<String> {
+        let language = locale.split(['-', '_']).next().unwrap_or(locale);
+
+        let text = self
+            .locales
+            .get(locale)
+            .or_else(|| self.locales.get(language))
+            .or_else(|| self.locales.get(fallback))?;
+
+        // fill parameters
+        let mut out = String::new();
+        let mut rest = text.as_str();
+
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let param = rest
+                .find('}')
+                .and_then(|end| params.get(&rest[1..end]).map(|v| (v, end)));
+
+            match param {
+                Some((value, end)) => {
+                    out.push_str(value);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    out.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        out.push_str(rest);
+        Some(out)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// Hours of the day (UTC) in which notifications aren't delivered in real time
+pub struct QuietHours {
+    /// First quiet hour (0-23)
+    pub start: u8,
+    /// First hour after the quiet hours (0-23), before `start` if they span midnight
+    pub end: u8,
+}
+
+impl QuietHours {
+    /// Check if the given time is in the quiet hours
+    ///
+    /// # Arguments:
+    /// * `timestamp` - time in milliseconds since epoch
+    pub fn contains(&self, timestamp: u128) -> bool {
+        let hour = ((timestamp / (1000 * 60 * 60)) % 24) as u8;
+
+        if self.start <= self.end {
+            (hour >= self.start) && (hour < self.end)
+        } else {
+            (hour >= self.start) || (hour < self.end)
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A user's notification preferences, stored in their metadata
+pub struct NotificationPreferences {
+    /// Services whose notifications are never stored
+    #[serde(default)]
+    pub muted_services: Vec<String>,
+    /// Hours in which notifications are stored but not delivered in real time
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+    /// Receive notifications in a digest instead of in real time
+    #[serde(default)]
+    pub digest: bool,
+}
+
+impl NotificationPreferences {
+    /// Check if notifications from the given service are muted
+    pub fn is_muted(&self, service: &str) -> bool {
+        self.muted_services.iter().any(|s| s == service)
+    }
+
+    /// Check if notifications created at the given time are delivered in real time
+    ///
+    /// # Arguments:
+    /// * `timestamp` - time in milliseconds since epoch
+    pub fn is_realtime(&self, timestamp: u128) -> bool {
+        !self.digest
+            && !self
+                .quiet_hours
+                .as_ref()
+                .is_some_and(|q| q.contains(timestamp))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// The result of [`NotificationDatabase::send_digests`]
+pub struct DigestReport {
+    /// Number of digests sent
+    pub sent: u64,
+    /// Number of notifications included in the sent digests
+    pub notifications: u64,
+    /// Number of digests that couldn't be sent
+    pub failed: u64,
+    /// Number of sent digests whose time couldn't be saved to the user
+    ///
+    /// The notifications of these digests are included again in the user's next digest.
+    pub unrecorded: u64,
+}
+
+/// [`LogKind`] of [`Notification`]s stored in the logs table
+///
+/// Notifications used to be stored as logs, see [`NotificationDatabase::migrate`].
+pub struct NotificationLog;
+
+impl LogKind for NotificationLog {
+    const LOGTYPE: &'static str = "notification";
+    type Payload = Notification;
 }
 
 // ...
@@ -42,12 +302,66 @@ impl<T: Default> Into<DefaultReturn<T>> for NotificationError {
 
 pub type Result<T> = std::result::Result<T, NotificationError>;
 
+// ...
+#[derive(Clone)]
+pub struct DatabaseOptions {
+    /// The table to use for database operations
+    pub table: String,
+    /// The prefix used in redis keys
+    pub prefix: String,
+    /// Maximum number of notifications inserted by a single query (bulk pushes)
+    pub batch_size: usize,
+    /// Registered [`NotificationTemplate`]s
+    pub templates: Vec<NotificationTemplate>,
+    /// Locale used for users without a preferred locale, and stored notification content
+    pub default_locale: String,
+    /// Time (in ms) after the latest notification of a group in which new notifications
+    /// of the same group are collapsed into it
+    pub group_window: u128,
+    /// Maximum number of actors kept on a grouped notification
+    pub max_actors: usize,
+    /// Subject of digest emails
+    pub digest_subject: String,
+    /// Maximum number of notifications listed in a digest email
+    pub digest_limit: usize,
+    /// Minimum age (in ms) of the notifications sent in digests to users who aren't
+    /// in digest mode (they may see them on the site first)
+    pub digest_delay: u128,
+    /// Prepended to relative notification addresses in digest emails (e.g. "https://example.com")
+    pub digest_base_url: String,
+    /// Maximum length (in characters) of the content and address of a notification
+    pub max_content_length: usize,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            table: String::from("Notifications"),
+            prefix: String::from("notification"),
+            batch_size: 500,
+            templates: Vec::new(),
+            default_locale: String::from("en"),
+            group_window: 3_600_000, // 1 hour
+            max_actors: 5,
+            digest_subject: String::from("Your unread notifications"),
+            digest_limit: 50,
+            digest_delay: 86_400_000, // 1 day
+            digest_base_url: String::new(),
+            max_content_length: 2048,
+        }
+    }
+}
+
 // database
 #[derive(Clone)]
 pub struct NotificationDatabase {
     pub base: StarterDatabase,
     pub auth: AuthDatabase,
     pub logs: LogDatabase,
+    pub options: DatabaseOptions,
+    /// Webhooks that new notifications are sent to (see [`NotificationDatabase::with_webhooks`])
+    #[cfg(feature = "webhooks")]
+    pub webhooks: Option<WebhookDatabase>,
 }
 
 impl NotificationDatabase {
@@ -55,98 +369,1383 @@ impl NotificationDatabase {
         base: StarterDatabase,
         auth: AuthDatabase,
         logs: LogDatabase,
+        options: DatabaseOptions,
     ) -> NotificationDatabase {
-        NotificationDatabase { base, auth, logs }
+        NotificationDatabase {
+            base,
+            auth,
+            logs,
+            options,
+            #[cfg(feature = "webhooks")]
+            webhooks: None,
+        }
+    }
+
+    /// Send new notifications to the webhooks of their recipient and service
+    /// (as "notification.created" events), services own webhooks as [`service_owner`]
+    ///
+    /// # Arguments:
+    /// * `webhooks` - [`WebhookDatabase`]
+    #[cfg(feature = "webhooks")]
+    pub fn with_webhooks(mut self, webhooks: WebhookDatabase) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Create the notifications table if it doesn't already exist
+    pub async fn init(&self) -> bool {
+        let c = &self.base.db.client;
+        let table = &self.options.table;
+
+        if sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{table}\" (
+                id VARCHAR(255) PRIMARY KEY,
+                recipient VARCHAR(255),
+                service VARCHAR(255),
+                content TEXT,
+                address TEXT,
+                timestamp BIGINT,
+                read_at BIGINT,
+                event VARCHAR(255),
+                params TEXT,
+                group_key VARCHAR(255),
+                count BIGINT,
+                actors TEXT,
+                open_group VARCHAR(255)
+            )"
+        ))
+        .execute(c)
+        .await
+        .is_err()
+        {
+            return false;
+        }
+
+        self.base
+            .create_index(
+                table,
+                &format!("{table}_recipient"),
+                &["recipient", "timestamp"],
+            )
+            .await
+            && self
+                .base
+                .create_index(table, &format!("{table}_unread"), &["recipient", "read_at"])
+                .await
+            && self
+                .base
+                .create_index(table, &format!("{table}_service"), &["service"])
+                .await
+            && self
+                .base
+                .create_unique_index(
+                    table,
+                    &format!("{table}_open_group"),
+                    &["recipient", "service", "open_group"],
+                )
+                .await
+    }
+
+    /// Move notifications stored in the logs table (before notifications had their
+    /// own table) into the notifications table
+    pub async fn migrate(&self) -> bool {
+        let (select, insert, delete) = if (self.base.db._type == "sqlite")
+            | (self.base.db._type == "mysql")
+        {
+            (
+                    format!(
+                        "SELECT * FROM \"{}\" WHERE \"logtype\" = ?",
+                        self.logs.options.table
+                    ),
+                    format!(
+                        "INSERT INTO \"{}\" (\"id\", \"recipient\", \"service\", \"content\", \"address\", \"timestamp\") VALUES (?, ?, ?, ?, ?, ?)",
+                        self.options.table
+                    ),
+                    format!(
+                        "DELETE FROM \"{}\" WHERE \"logtype\" = ?",
+                        self.logs.options.table
+                    ),
+                )
+        } else {
+            (
+                    format!(
+                        "SELECT * FROM \"{}\" WHERE \"logtype\" = $1",
+                        self.logs.options.table
+                    ),
+                    format!(
+                        "INSERT INTO \"{}\" (\"id\", \"recipient\", \"service\", \"content\", \"address\", \"timestamp\") VALUES ($1, $2, $3, $4, $5, $6)",
+                        self.options.table
+                    ),
+                    format!(
+                        "DELETE FROM \"{}\" WHERE \"logtype\" = $1",
+                        self.logs.options.table
+                    ),
+                )
+        };
+
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return false,
+        };
+
+        let logs = match sqlx::query_as::<_, Log>(&select)
+            .bind::<&str>(NotificationLog::LOGTYPE)
+            .fetch_all(&mut *transaction)
+            .await
+        {
+            Ok(l) => l,
+            Err(_) => return false,
+        };
+
+        for log in logs {
+            let log = match log.decode::<NotificationLog>() {
+                Ok(l) => l,
+                Err(_) => continue,
+            };
+
+            let notification = log.payload;
+
+            if sqlx::query(&insert)
+                .bind::<&String>(&log.id)
+                .bind::<&String>(&notification.user)
+                .bind::<&String>(&notification.service)
+                .bind::<&String>(&notification.content)
+                .bind::<&String>(&notification.address)
+                .bind::<i64>(log.timestamp as i64)
+                .execute(&mut *transaction)
+                .await
+                .is_err()
+            {
+                return false;
+            }
+        }
+
+        if sqlx::query(&delete)
+            .bind::<&str>(NotificationLog::LOGTYPE)
+            .execute(&mut *transaction)
+            .await
+            .is_err()
+        {
+            return false;
+        }
+
+        transaction.commit().await.is_ok()
     }
 
     // notifications
 
     // GET
-    /// Get the [`Notification`]s that belong to the given `user`
+    /// Get the [`Notification`]s that belong to the given `user`, newest first
     ///
     /// # Arguments:
     /// * `user` - username of user to check
+    /// * `offset` - optional pagination offset (pages are 50 notifications)
     pub async fn get_user_notifications(
         &self,
         user: String,
         offset: Option<i32>,
-    ) -> Result<Vec<Log>> {
+    ) -> Result<Vec<Notification>> {
         let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            format!("SELECT * FROM \"{}\" WHERE \"content\" LIKE ? AND \"logtype\" = 'notification' ORDER BY \"timestamp\" DESC LIMIT 50 OFFSET ?", self.logs.options.table)
+            format!("SELECT * FROM \"{}\" WHERE \"recipient\" = ? ORDER BY \"timestamp\" DESC, \"id\" DESC LIMIT 50 OFFSET ?", self.options.table)
         } else {
-            format!("SELECT * FROM \"{}\" WHERE \"content\" LIKE $1 AND \"logtype\" = 'notification' ORDER BY \"timestamp\" DESC LIMIT 50 OFFSET $2", self.logs.options.table)
+            format!("SELECT * FROM \"{}\" WHERE \"recipient\" = $1 ORDER BY \"timestamp\" DESC, \"id\" DESC LIMIT 50 OFFSET $2", self.options.table)
         };
 
-        let c = &self.base.db.client;
-        let rows = match sqlx::query(&query)
-            .bind::<&String>(&format!("%\"user\":\"{user}\"%"))
-            .bind(if offset.is_some() { offset.unwrap() } else { 0 })
-            .fetch_all(c)
+        match self
+            .base
+            .fetch_all_as::<Notification>(
+                sqlx::query(&query)
+                    .bind::<&String>(&user)
+                    .bind(if offset.is_some() { offset.unwrap() } else { 0 }),
+            )
             .await
         {
-            Ok(r) => r,
-            Err(_) => return Err(NotificationError::Other),
+            Ok(n) => Ok(self.localize(&user, n).await),
+            Err(_) => Err(NotificationError::Other),
+        }
+    }
+
+    /// Get a [`Notification`] by its `id`
+    ///
+    /// # Arguments:
+    /// * `user` - username of the notification's recipient
+    /// * `id` - `String` of the notification's id
+    pub async fn get_notification(&self, user: String, id: String) -> Result<Notification> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"id\" = ? AND \"recipient\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"id\" = $1 AND \"recipient\" = $2",
+                self.options.table
+            )
         };
 
-        // ...
-        let mut output: Vec<Log> = Vec::new();
+        match self
+            .base
+            .fetch_one_as::<Notification>(
+                sqlx::query(&query)
+                    .bind::<&String>(&id)
+                    .bind::<&String>(&user),
+            )
+            .await
+        {
+            Ok(n) => Ok(self.localize(&user, vec![n]).await.remove(0)),
+            Err(sqlx::Error::RowNotFound) => Err(NotificationError::NotFound),
+            Err(_) => Err(NotificationError::Other),
+        }
+    }
+
+    /// Get the [`Notification`]s of the given `user` created after the notification
+    /// with the given `id`, oldest first (at most 50)
+    ///
+    /// # Arguments:
+    /// * `user` - username of the notifications' recipient
+    /// * `id` - `String` of the notification's id
+    pub async fn get_user_notifications_after(
+        &self,
+        user: String,
+        id: String,
+    ) -> Result<Vec<Notification>> {
+        let after = self.get_notification(user.clone(), id).await?;
 
-        for row in rows {
-            let row = self.base.textify_row(row).data;
-            output.push(Log {
-                id: row.get("id").unwrap().to_string(),
-                logtype: row.get("logtype").unwrap().to_string(),
-                timestamp: row.get("timestamp").unwrap().parse::<u128>().unwrap(),
-                content: row.get("content").unwrap().to_string(),
-            });
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("SELECT * FROM \"{}\" WHERE \"recipient\" = ? AND (\"timestamp\" > ? OR (\"timestamp\" = ? AND \"id\" > ?)) ORDER BY \"timestamp\" ASC, \"id\" ASC LIMIT 50", self.options.table)
+        } else {
+            format!("SELECT * FROM \"{}\" WHERE \"recipient\" = $1 AND (\"timestamp\" > $2 OR (\"timestamp\" = $3 AND \"id\" > $4)) ORDER BY \"timestamp\" ASC, \"id\" ASC LIMIT 50", self.options.table)
+        };
+
+        match self
+            .base
+            .fetch_all_as::<Notification>(
+                sqlx::query(&query)
+                    .bind::<&String>(&user)
+                    .bind::<i64>(after.timestamp as i64)
+                    .bind::<i64>(after.timestamp as i64)
+                    .bind::<&String>(&after.id),
+            )
+            .await
+        {
+            Ok(n) => Ok(self.localize(&user, n).await),
+            Err(_) => Err(NotificationError::Other),
         }
+    }
 
-        // return
-        Ok(output)
+    // templates
+
+    /// Get the [`NotificationTemplate`] registered for the given service and event type
+    ///
+    /// # Arguments:
+    /// * `service` - service the template belongs to
+    /// * `event` - event type of the template
+    pub fn get_template(&self, service: &str, event: &str) -> Option<&NotificationTemplate> {
+        self.options
+            .templates
+            .iter()
+            .find(|t| (t.service == service) && (t.event == event))
+    }
+
+    /// Render the content of a [`Notification`] from its template
+    ///
+    /// Notifications without an event type (or without a registered template) keep
+    /// their content. The `count` and `actors` parameters are filled from the notification
+    /// unless they are given.
+    ///
+    /// # Arguments:
+    /// * `notification` - [`Notification`]
+    /// * `locale` - preferred locale, [`DatabaseOptions::default_locale`] if none
+    pub fn render(&self, notification: &mut Notification, locale: Option<&str>) {
+        let template = match notification.event {
+            Some(ref event) => match self.get_template(&notification.service, event) {
+                Some(t) => t,
+                None => return,
+            },
+            None => return,
+        };
+
+        let default_locale = &self.options.default_locale;
+
+        let mut params = notification.params.clone();
+        params
+            .entry(String::from("count"))
+            .or_insert(notification.count.to_string());
+        params
+            .entry(String::from("actors"))
+            .or_insert(notification.actors.join(", "));
+
+        if let Some(content) =
+            template.render(locale.unwrap_or(default_locale), default_locale, &params)
+        {
+            notification.content = content;
+        }
     }
 
-    /// Check if the given `user` has an active notification
+    /// Render the given `user`'s [`Notification`]s in their preferred locale
+    ///
+    /// # Arguments:
+    /// * `user` - username of the notifications' recipient
+    /// * `notifications` - the notifications
+    async fn localize(
+        &self,
+        user: &str,
+        mut notifications: Vec<Notification>,
+    ) -> Vec<Notification> {
+        if notifications.iter().all(|n| n.event.is_none()) {
+            return notifications;
+        }
+
+        let locale = match self.auth.get_user_by_username(user.to_string()).await {
+            Ok(ua) => ua.user.metadata.locale,
+            Err(_) => None,
+        };
+
+        for notification in notifications.iter_mut() {
+            self.render(notification, locale.as_deref());
+        }
+
+        notifications
+    }
+
+    /// Check if the given `user` has an unread notification
     ///
     /// # Arguments:
     /// * `user` - username of user to check
     pub async fn user_has_notification(&self, user: String) -> Result<bool> {
+        Ok(self.unread_count(user).await? > 0)
+    }
+
+    /// Get the number of unread [`Notification`]s that belong to the given `user`
+    ///
+    /// The count is cached until the user's notifications are created, read, or deleted.
+    ///
+    /// # Arguments:
+    /// * `user` - username of user to check
+    pub async fn unread_count(&self, user: String) -> Result<i64> {
+        // check in cache, counts cached before the last change are stale
+        let version = self
+            .base
+            .cachedb
+            .get(self.unread_version_key(&user))
+            .await
+            .unwrap_or(String::from("0"));
+
+        let cached = self.base.cachedb.get(self.unread_key(&user)).await;
+
+        if let Some((cached_version, count)) = cached.as_ref().and_then(|c| c.split_once(':')) {
+            if let (true, Ok(count)) = (cached_version == version, count.parse::<i64>()) {
+                return Ok(count);
+            }
+        }
+
+        // ...
         let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
-            format!("SELECT * FROM \"{}\" WHERE \"content\" LIKE ? AND \"logtype\" = 'notification' LIMIT 1", self.logs.options.table)
+            format!(
+                "SELECT COUNT(*) FROM \"{}\" WHERE \"recipient\" = ? AND \"read_at\" IS NULL",
+                self.options.table
+            )
         } else {
-            format!("SELECT * FROM \"{}\" WHERE \"content\" LIKE $1 AND \"logtype\" = 'notification' LIMIT 1", self.logs.options.table)
+            format!(
+                "SELECT COUNT(*) FROM \"{}\" WHERE \"recipient\" = $1 AND \"read_at\" IS NULL",
+                self.options.table
+            )
         };
 
-        let c = &self.base.db.client;
-        match sqlx::query(&query)
-            .bind::<&String>(&format!("%\"user\":\"{user}\"%"))
-            .fetch_one(c)
+        let count = match self
+            .base
+            .fetch_one_as::<(i64,)>(sqlx::query(&query).bind::<&String>(&user))
             .await
         {
-            Ok(_) => Ok(false),
-            Err(_) => Ok(true),
+            Ok((count,)) => count,
+            Err(_) => return Err(NotificationError::Other),
+        };
+
+        // store in cache (with the version read before counting, so a change made while
+        // counting makes it stale)
+        self.base
+            .cachedb
+            .set_expiring(
+                self.unread_key(&user),
+                format!("{version}:{count}"),
+                UNREAD_COUNT_TTL,
+            )
+            .await;
+
+        // return
+        Ok(count)
+    }
+
+    /// Get the cache key of a user's unread count
+    fn unread_key(&self, user: &str) -> String {
+        format!("{}:unread:{}", self.options.prefix, user)
+    }
+
+    /// Get the cache key of the version of a user's unread count
+    fn unread_version_key(&self, user: &str) -> String {
+        format!("{}:unread_version:{}", self.options.prefix, user)
+    }
+
+    /// Mark a user's cached unread count as stale, it is counted again on the next read
+    ///
+    /// # Arguments:
+    /// * `user` - username of the user
+    async fn invalidate_unread_count(&self, user: &str) {
+        self.base.cachedb.incr(self.unread_version_key(user)).await;
+    }
+
+    /// Publish a new [`Notification`] to subscribers of its recipient
+    async fn publish_notification(&self, notification: &Notification) {
+        self.base
+            .pubsub
+            .publish(
+                format!("{}:new:{}", self.options.prefix, notification.user),
+                serde_json::to_string::<Notification>(notification).unwrap(),
+            )
+            .await;
+    }
+
+    /// Deliver a new [`Notification`] in real time (to subscribers and webhooks)
+    async fn deliver_notification(&self, notification: &Notification) {
+        self.publish_notification(notification).await;
+
+        #[cfg(feature = "webhooks")]
+        if let Some(ref webhooks) = self.webhooks {
+            let _ = webhooks
+                .dispatch(
+                    vec![
+                        notification.user.clone(),
+                        service_owner(&notification.service),
+                    ],
+                    "notification.created",
+                    notification,
+                )
+                .await;
+        }
+    }
+
+    /// Subscribe to new [`Notification`]s of the given `user`
+    ///
+    /// Every notification created through [`NotificationDatabase::push_user_notification`]
+    /// after subscribing is sent to the stream. If `last_id` is given, every notification
+    /// created after it is sent first (see [`NotificationDatabase::get_user_notifications_after`]),
+    /// so clients can resume after reconnecting. Grouped notifications are sent again
+    /// whenever another notification is merged into them.
+    ///
+    /// # Arguments:
+    /// * `user` - username of the notifications' recipient
+    /// * `last_id` - id of the last notification the client received
+    pub async fn subscribe(
+        &self,
+        user: String,
+        last_id: Option<String>,
+    ) -> Result<BoxStream<'static, Notification>> {
+        // subscribe before fetching missed notifications so none are lost in between
+        let live = match self
+            .base
+            .pubsub
+            .subscribe(format!("{}:new:{}", self.options.prefix, user))
+            .await
+        {
+            Some(s) => s,
+            None => return Err(NotificationError::Other),
+        };
+
+        // fetch every page of missed notifications
+        let mut missed: Vec<Notification> = Vec::new();
+        let mut after = last_id;
+
+        while let Some(id) = after.take() {
+            let page = match self.get_user_notifications_after(user.clone(), id).await {
+                Ok(n) => n,
+                // the last notification may have been deleted
+                Err(NotificationError::NotFound) => Vec::new(),
+                Err(e) => return Err(e),
+            };
+
+            if page.len() == 50 {
+                after = page.last().map(|n| n.id.clone());
+            }
+
+            missed.extend(page);
+        }
+
+        // live copies of replayed notifications are only sent if they were merged into since
+        let sent: HashMap<String, u32> = missed.iter().map(|n| (n.id.clone(), n.count)).collect();
+
+        Ok(stream::iter(missed)
+            .chain(live.filter_map(move |message| {
+                let notification = serde_json::from_str::<Notification>(&message)
+                    .ok()
+                    .filter(|n| sent.get(&n.id).is_none_or(|count| n.count > *count));
+
+                async move { notification }
+            }))
+            .boxed())
+    }
+
+    // preferences
+
+    /// Get the [`NotificationPreferences`] of the given `user`
+    ///
+    /// # Arguments:
+    /// * `user` - username of the user
+    pub async fn get_preferences(&self, user: String) -> Result<NotificationPreferences> {
+        match self.auth.get_user_by_username(user).await {
+            Ok(ua) => Ok(ua.user.metadata.notifications),
+            Err(_) => Err(NotificationError::NotFound),
+        }
+    }
+
+    /// Update the [`NotificationPreferences`] of the given `user`
+    ///
+    /// # Arguments:
+    /// * `user` - username of the user
+    /// * `preferences` - [`NotificationPreferences`]
+    pub async fn set_preferences(
+        &self,
+        user: String,
+        preferences: NotificationPreferences,
+    ) -> Result<()> {
+        if preferences
+            .quiet_hours
+            .as_ref()
+            .is_some_and(|q| (q.start > 23) | (q.end > 23))
+        {
+            return Err(NotificationError::ValueError);
+        }
+
+        // make sure user exists
+        if self.auth.get_user_by_username(user.clone()).await.is_err() {
+            return Err(NotificationError::NotFound);
+        }
+
+        match self
+            .auth
+            .update_user_metadata(user, |m| m.notifications = preferences)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(NotificationError::Other),
         }
     }
 
     // SET
     /// Create a new [`Notification`] for a given [`UserState`]
     ///
+    /// Returns the created notification, or none if the user muted its service. Users
+    /// in quiet hours or digest mode don't receive the notification in real time.
+    /// Notifications are checked with [`NotificationDatabase::validate_notification`].
+    ///
+    /// Notifications with a `group` are collapsed into the user's unread notification
+    /// of the same service and group if it was updated within [`DatabaseOptions::group_window`],
+    /// in which case the updated notification is returned.
+    ///
     /// # Arguments:
     /// * `props` - [`Notification`]
-    pub async fn push_user_notification(&self, props: &mut Notification) -> LogResult<()> {
-        let p: &mut Notification = props; // borrowed props
+    pub async fn push_user_notification(
+        &self,
+        props: Notification,
+    ) -> Result<Option<Notification>> {
+        let mut p = props;
 
         // make sure user exists
-        match self.auth.get_user_by_username(p.user.to_owned()).await {
-            Ok(ua) => ua,
-            Err(_) => return Err(LogError::Other),
+        let metadata = match self.auth.get_user_by_username(p.user.to_owned()).await {
+            Ok(ua) => ua.user.metadata,
+            Err(_) => return Err(NotificationError::NotFound),
+        };
+
+        let preferences = metadata.notifications;
+
+        if preferences.is_muted(&p.service) {
+            return Ok(None);
+        }
+
+        // ...
+        p.id = utility::random_id();
+        p.timestamp = utility::unix_epoch_timestamp();
+        p.read_at = None;
+        p.count = 1;
+        p.actors.truncate(self.options.max_actors);
+        self.render(&mut p, None);
+        self.validate_notification(&p)?;
+
+        let c = &self.base.db.client;
+
+        let merged = if p.group.is_some() {
+            self.store_grouped(&p).await?
+        } else {
+            let query: String = if (self.base.db._type == "sqlite")
+                | (self.base.db._type == "mysql")
+            {
+                format!(
+                    "INSERT INTO \"{}\" VALUES (?, ?, ?, ?, ?, ?, NULL, ?, ?, ?, ?, ?, NULL)",
+                    self.options.table
+                )
+            } else {
+                format!(
+                        "INSERT INTO \"{}\" VALUES ($1, $2, $3, $4, $5, $6, NULL, $7, $8, $9, $10, $11, NULL)",
+                        self.options.table
+                    )
+            };
+
+            if sqlx::query(&query)
+                .bind::<&String>(&p.id)
+                .bind::<&String>(&p.user)
+                .bind::<&String>(&p.service)
+                .bind::<&String>(&p.content)
+                .bind::<&String>(&p.address)
+                .bind::<i64>(p.timestamp as i64)
+                .bind::<&Option<String>>(&p.event)
+                .bind::<String>(serde_json::to_string(&p.params).unwrap())
+                .bind::<&Option<String>>(&p.group)
+                .bind::<i64>(p.count as i64)
+                .bind::<String>(serde_json::to_string(&p.actors).unwrap())
+                .execute(c)
+                .await
+                .is_err()
+            {
+                return Err(NotificationError::Other);
+            }
+
+            None
+        };
+
+        let mut p = match merged {
+            Some(merged) => merged,
+            None => {
+                self.invalidate_unread_count(&p.user).await;
+                p
+            }
+        };
+
+        self.render(&mut p, metadata.locale.as_deref());
+
+        if preferences.is_realtime(p.timestamp) {
+            self.deliver_notification(&p).await;
+        }
+
+        Ok(Some(p))
+    }
+
+    /// Check that the fields of a [`Notification`] are valid
+    ///
+    /// The content must not be empty, text fields must fit in their column or
+    /// [`DatabaseOptions::max_content_length`], and the address must be empty, an http(s)
+    /// url, or a path relative to the site (starting with a single "/").
+    ///
+    /// # Arguments:
+    /// * `notification` - [`Notification`] (with its content already rendered)
+    pub fn validate_notification(&self, notification: &Notification) -> Result<()> {
+        let max = self.options.max_content_length;
+
+        if notification.content.trim().is_empty()
+            | (notification.content.chars().count() > max)
+            | (notification.address.chars().count() > max)
+            | (notification.service.is_empty())
+            | (notification.service.len() > 255)
+            | notification.event.as_ref().is_some_and(|e| e.len() > 255)
+            | notification.group.as_ref().is_some_and(|g| g.len() > 255)
+            | notification.actors.iter().any(|a| a.len() > 255)
+        {
+            return Err(NotificationError::ValueError);
+        }
+
+        // address
+        let address = &notification.address;
+
+        if address.is_empty() {
+            return Ok(());
+        }
+
+        if address.chars().any(|c| c.is_control() | c.is_whitespace()) {
+            return Err(NotificationError::ValueError);
+        }
+
+        // "//host" and "/\host" are treated as urls of another host by browsers
+        if address.starts_with('/') {
+            return match address.chars().nth(1) {
+                Some('/') | Some('\\') => Err(NotificationError::ValueError),
+                _ => Ok(()),
+            };
+        }
+
+        let lowercase = address.to_lowercase();
+        let host = match lowercase
+            .strip_prefix("https://")
+            .or_else(|| lowercase.strip_prefix("http://"))
+        {
+            Some(rest) => rest.split(['/', '?', '#']).next().unwrap_or(""),
+            None => return Err(NotificationError::ValueError),
+        };
+
+        if host.is_empty() {
+            return Err(NotificationError::ValueError);
+        }
+
+        Ok(())
+    }
+
+    /// Store a new [`Notification`] with a `group`, collapsing it into the open
+    /// notification of its group if there is one
+    ///
+    /// Returns the updated notification, or none if the notification was inserted as
+    /// the new open notification of its group. Conflicts with concurrent pushes to the
+    /// same group are retried.
+    ///
+    /// # Arguments:
+    /// * `notification` - the new [`Notification`] (with its `group` set)
+    async fn store_grouped(&self, notification: &Notification) -> Result<Option<Notification>> {
+        let mut retries = 0;
+
+        loop {
+            match self.try_store_grouped(notification).await {
+                Ok(merged) => return Ok(merged),
+                // a concurrent push opened the group first (merge into it instead), or
+                // deadlocked with this one
+                Err(e) if (retries < GROUPED_RETRIES) & is_conflict(&e) => retries += 1,
+                Err(_) => return Err(NotificationError::Other),
+            }
+        }
+    }
+
+    /// Collapse a new [`Notification`] into the open notification of its group, or
+    /// insert it as the group's open notification
+    ///
+    /// Each group has at most one open notification per user and service (enforced by
+    /// the `open_group` column's unique index). A group is closed once its notification
+    /// is read or older than [`DatabaseOptions::group_window`].
+    ///
+    /// # Arguments:
+    /// * `notification` - the new [`Notification`] (with its `group` set)
+    async fn try_store_grouped(
+        &self,
+        notification: &Notification,
+    ) -> sqlx::Result<Option<Notification>> {
+        let table = &self.options.table;
+        let (close, select, update, insert) = if (self.base.db._type == "sqlite")
+            | (self.base.db._type == "mysql")
+        {
+            (
+                    format!("UPDATE \"{table}\" SET \"open_group\" = NULL WHERE \"recipient\" = ? AND \"service\" = ? AND \"open_group\" = ? AND (\"read_at\" IS NOT NULL OR \"timestamp\" < ?)"),
+                    format!("SELECT * FROM \"{table}\" WHERE \"recipient\" = ? AND \"service\" = ? AND \"open_group\" = ?"),
+                    format!("UPDATE \"{table}\" SET \"content\" = ?, \"address\" = ?, \"event\" = ?, \"params\" = ?, \"count\" = \"count\" + 1, \"actors\" = ?, \"timestamp\" = ? WHERE \"id\" = ?"),
+                    format!("INSERT INTO \"{table}\" VALUES (?, ?, ?, ?, ?, ?, NULL, ?, ?, ?, ?, ?, ?)"),
+                )
+        } else {
+            (
+                    format!("UPDATE \"{table}\" SET \"open_group\" = NULL WHERE \"recipient\" = $1 AND \"service\" = $2 AND \"open_group\" = $3 AND (\"read_at\" IS NOT NULL OR \"timestamp\" < $4)"),
+                    format!("SELECT * FROM \"{table}\" WHERE \"recipient\" = $1 AND \"service\" = $2 AND \"open_group\" = $3"),
+                    format!("UPDATE \"{table}\" SET \"content\" = $1, \"address\" = $2, \"event\" = $3, \"params\" = $4, \"count\" = \"count\" + 1, \"actors\" = $5, \"timestamp\" = $6 WHERE \"id\" = $7"),
+                    format!("INSERT INTO \"{table}\" VALUES ($1, $2, $3, $4, $5, $6, NULL, $7, $8, $9, $10, $11, $12)"),
+                )
+        };
+
+        // lock the open notification until the transaction ends (sqlite already holds
+        // the write lock after the first update)
+        let select = if self.base.db._type == "sqlite" {
+            select
+        } else {
+            format!("{select} FOR UPDATE")
+        };
+
+        let c = &self.base.db.client;
+        let mut transaction = c.begin().await?;
+
+        // close the group if its notification was read or is too old
+        let since = notification
+            .timestamp
+            .saturating_sub(self.options.group_window);
+
+        sqlx::query(&close)
+            .bind::<&String>(&notification.user)
+            .bind::<&String>(&notification.service)
+            .bind::<&Option<String>>(&notification.group)
+            .bind::<i64>(since as i64)
+            .execute(&mut *transaction)
+            .await?;
+
+        // get open notification of the group
+        let existing = sqlx::query_as::<_, Notification>(&select)
+            .bind::<&String>(&notification.user)
+            .bind::<&String>(&notification.service)
+            .bind::<&Option<String>>(&notification.group)
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+        let existing = match existing {
+            Some(n) => n,
+            None => {
+                // open the group
+                sqlx::query(&insert)
+                    .bind::<&String>(&notification.id)
+                    .bind::<&String>(&notification.user)
+                    .bind::<&String>(&notification.service)
+                    .bind::<&String>(&notification.content)
+                    .bind::<&String>(&notification.address)
+                    .bind::<i64>(notification.timestamp as i64)
+                    .bind::<&Option<String>>(&notification.event)
+                    .bind::<String>(serde_json::to_string(&notification.params).unwrap())
+                    .bind::<&Option<String>>(&notification.group)
+                    .bind::<i64>(notification.count as i64)
+                    .bind::<String>(serde_json::to_string(&notification.actors).unwrap())
+                    .bind::<&Option<String>>(&notification.group)
+                    .execute(&mut *transaction)
+                    .await?;
+
+                transaction.commit().await?;
+                return Ok(None);
+            }
+        };
+
+        // merge
+        let mut actors = notification.actors.clone();
+
+        for actor in existing.actors {
+            if !actors.contains(&actor) {
+                actors.push(actor);
+            }
+        }
+
+        actors.truncate(self.options.max_actors);
+
+        let mut merged = Notification {
+            id: existing.id,
+            count: existing.count.saturating_add(1),
+            actors,
+            ..notification.clone()
+        };
+
+        // count and actors changed
+        self.render(&mut merged, None);
+
+        sqlx::query(&update)
+            .bind::<&String>(&merged.content)
+            .bind::<&String>(&merged.address)
+            .bind::<&Option<String>>(&merged.event)
+            .bind::<String>(serde_json::to_string(&merged.params).unwrap())
+            .bind::<String>(serde_json::to_string(&merged.actors).unwrap())
+            .bind::<i64>(merged.timestamp as i64)
+            .bind::<&String>(&merged.id)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+
+        // return
+        Ok(Some(merged))
+    }
+
+    /// Get a list of query placeholders
+    ///
+    /// # Arguments:
+    /// * `count` - number of placeholders
+    /// * `start` - index of the first placeholder (postgres only)
+    fn placeholders(&self, count: usize, start: usize) -> Vec<String> {
+        if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            vec![String::from("?"); count]
+        } else {
+            (start..start + count).map(|i| format!("${i}")).collect()
+        }
+    }
+
+    /// Create a copy of `template` for each of the given users
+    ///
+    /// Users who muted the template's service are skipped. All notifications are
+    /// inserted in a single transaction, and aren't collapsed into existing groups.
+    /// The template is checked with [`NotificationDatabase::validate_notification`].
+    ///
+    /// # Arguments:
+    /// * `users` - the recipients
+    /// * `template` - [`Notification`] (its `user` is ignored)
+    async fn push_to_users(
+        &self,
+        users: Vec<UserState<UserMetadata>>,
+        template: &Notification,
+    ) -> Result<Vec<Notification>> {
+        let timestamp = utility::unix_epoch_timestamp();
+
+        let mut template = template.clone();
+        template.count = 1;
+        template.actors.truncate(self.options.max_actors);
+        self.render(&mut template, None);
+        self.validate_notification(&template)?;
+
+        let recipients: Vec<(Notification, UserMetadata)> = users
+            .into_iter()
+            .filter(|u| !u.metadata.notifications.is_muted(&template.service))
+            .map(|u| {
+                (
+                    Notification {
+                        id: utility::random_id(),
+                        user: u.username,
+                        timestamp,
+                        read_at: None,
+                        ..template.clone()
+                    },
+                    u.metadata,
+                )
+            })
+            .collect();
+
+        // insert
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(NotificationError::Other),
+        };
+
+        for batch in recipients.chunks(self.options.batch_size.max(1)) {
+            let values = (0..batch.len())
+                .map(|i| {
+                    let p = self.placeholders(11, i * 11 + 1);
+                    format!("({}, NULL, {}, NULL)", p[..6].join(", "), p[6..].join(", "))
+                })
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            let query = format!("INSERT INTO \"{}\" VALUES {values}", self.options.table);
+            let mut q = sqlx::query(&query);
+
+            for (n, _) in batch {
+                q = q
+                    .bind::<&String>(&n.id)
+                    .bind::<&String>(&n.user)
+                    .bind::<&String>(&n.service)
+                    .bind::<&String>(&n.content)
+                    .bind::<&String>(&n.address)
+                    .bind::<i64>(n.timestamp as i64)
+                    .bind::<&Option<String>>(&n.event)
+                    .bind::<String>(serde_json::to_string(&n.params).unwrap())
+                    .bind::<&Option<String>>(&n.group)
+                    .bind::<i64>(n.count as i64)
+                    .bind::<String>(serde_json::to_string(&n.actors).unwrap());
+            }
+
+            if q.execute(&mut *transaction).await.is_err() {
+                return Err(NotificationError::Other);
+            }
+        }
+
+        if transaction.commit().await.is_err() {
+            return Err(NotificationError::Other);
+        }
+
+        // deliver
+        let mut output: Vec<Notification> = Vec::new();
+
+        for (mut n, metadata) in recipients {
+            self.invalidate_unread_count(&n.user).await;
+            self.render(&mut n, metadata.locale.as_deref());
+
+            if metadata.notifications.is_realtime(timestamp) {
+                self.deliver_notification(&n).await;
+            }
+
+            output.push(n);
+        }
+
+        // return
+        Ok(output)
+    }
+
+    /// Create a copy of a [`Notification`] for each of the given users
+    ///
+    /// Users that don't exist (or are banned) are skipped. Returns the created notifications.
+    ///
+    /// # Arguments:
+    /// * `users` - usernames of the recipients
+    /// * `template` - [`Notification`] (its `user` is ignored)
+    pub async fn push_bulk(
+        &self,
+        users: Vec<String>,
+        template: Notification,
+    ) -> Result<Vec<Notification>> {
+        let mut recipients: Vec<UserState<UserMetadata>> = Vec::new();
+
+        for batch in users.chunks(self.options.batch_size.max(1)) {
+            let query = format!(
+                "SELECT * FROM \"{}\" WHERE \"username\" IN ({}) AND \"role\" != 'banned'",
+                self.auth.options.table,
+                self.placeholders(batch.len(), 1).join(", ")
+            );
+
+            let mut q = sqlx::query(&query);
+
+            for user in batch {
+                q = q.bind::<&String>(user);
+            }
+
+            match self.base.fetch_all_as::<UserState<UserMetadata>>(q).await {
+                Ok(u) => recipients.extend(u),
+                Err(_) => return Err(NotificationError::Other),
+            }
+        }
+
+        self.push_to_users(recipients, &template).await
+    }
+
+    /// Create a copy of a [`Notification`] for every user (or every user with the given role)
+    ///
+    /// Banned users are skipped. Returns the number of created notifications.
+    ///
+    /// # Arguments:
+    /// * `role` - role of the recipients, all users if none
+    /// * `template` - [`Notification`] (its `user` is ignored)
+    pub async fn broadcast(&self, role: Option<String>, template: Notification) -> Result<usize> {
+        let query = match role {
+            Some(_) => format!(
+                "SELECT * FROM \"{}\" WHERE \"role\" = {} AND \"role\" != 'banned'",
+                self.auth.options.table,
+                self.placeholders(1, 1)[0]
+            ),
+            None => format!(
+                "SELECT * FROM \"{}\" WHERE \"role\" != 'banned'",
+                self.auth.options.table
+            ),
+        };
+
+        let mut q = sqlx::query(&query);
+
+        if let Some(ref role) = role {
+            q = q.bind::<&String>(role);
+        }
+
+        let recipients = match self.base.fetch_all_as::<UserState<UserMetadata>>(q).await {
+            Ok(u) => u,
+            Err(_) => return Err(NotificationError::Other),
+        };
+
+        Ok(self.push_to_users(recipients, &template).await?.len())
+    }
+
+    // digests
+
+    /// Send an email digest of their unread [`Notification`]s to every user with an email address
+    ///
+    /// Digests include the unread notifications created since the user's last digest. Users
+    /// who aren't in digest mode only get notifications older than [`DatabaseOptions::digest_delay`],
+    /// and users in quiet hours are skipped until the next run. Users whose digest fails
+    /// are counted in the returned [`DigestReport`], and the run continues with the next user.
+    ///
+    /// # Arguments:
+    /// * `mailer` - [`Mailer`] used to send the digests
+    pub async fn send_digests(&self, mailer: &dyn Mailer) -> Result<DigestReport> {
+        // users with unread notifications
+        let recipients_query = format!(
+            "SELECT * FROM \"{}\" WHERE \"username\" IN (SELECT DISTINCT \"recipient\" FROM \"{}\" WHERE \"read_at\" IS NULL)",
+            self.auth.options.table, self.options.table
+        );
+
+        let (select, count) = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql")
+        {
+            (
+                format!("SELECT * FROM \"{}\" WHERE \"recipient\" = ? AND \"read_at\" IS NULL AND \"timestamp\" > ? AND \"timestamp\" <= ? ORDER BY \"timestamp\" DESC LIMIT {}", self.options.table, self.options.digest_limit),
+                format!("SELECT COUNT(*) FROM \"{}\" WHERE \"recipient\" = ? AND \"read_at\" IS NULL AND \"timestamp\" > ? AND \"timestamp\" <= ?", self.options.table),
+            )
+        } else {
+            (
+                format!("SELECT * FROM \"{}\" WHERE \"recipient\" = $1 AND \"read_at\" IS NULL AND \"timestamp\" > $2 AND \"timestamp\" <= $3 ORDER BY \"timestamp\" DESC LIMIT {}", self.options.table, self.options.digest_limit),
+                format!("SELECT COUNT(*) FROM \"{}\" WHERE \"recipient\" = $1 AND \"read_at\" IS NULL AND \"timestamp\" > $2 AND \"timestamp\" <= $3", self.options.table),
+            )
+        };
+
+        let recipients = match self
+            .base
+            .fetch_all_as::<UserState<UserMetadata>>(sqlx::query(&recipients_query))
+            .await
+        {
+            Ok(r) => r,
+            Err(_) => return Err(NotificationError::Other),
         };
 
+        let mut report = DigestReport::default();
+        let now = utility::unix_epoch_timestamp();
+
+        for recipient in recipients {
+            let user = recipient.username;
+            let metadata = recipient.metadata;
+
+            let email = match metadata.email {
+                Some(ref e) if !e.is_empty() => e.clone(),
+                _ => continue,
+            };
+
+            let preferences = &metadata.notifications;
+
+            if preferences
+                .quiet_hours
+                .as_ref()
+                .is_some_and(|q| q.contains(now))
+            {
+                continue;
+            }
+
+            let since = metadata.last_digest.unwrap_or(0);
+            let until = if preferences.digest {
+                now
+            } else {
+                now.saturating_sub(self.options.digest_delay)
+            };
+
+            if until <= since {
+                continue;
+            }
+
+            // get notifications
+            let mut notifications = match self
+                .base
+                .fetch_all_as::<Notification>(
+                    sqlx::query(&select)
+                        .bind::<&String>(&user)
+                        .bind::<i64>(since as i64)
+                        .bind::<i64>(until as i64),
+                )
+                .await
+            {
+                Ok(n) => n,
+                Err(_) => {
+                    report.failed += 1;
+                    continue;
+                }
+            };
+
+            if notifications.is_empty() {
+                continue;
+            }
+
+            let total = match self
+                .base
+                .fetch_one_as::<(i64,)>(
+                    sqlx::query(&count)
+                        .bind::<&String>(&user)
+                        .bind::<i64>(since as i64)
+                        .bind::<i64>(until as i64),
+                )
+                .await
+            {
+                Ok((total,)) => total as usize,
+                Err(_) => {
+                    report.failed += 1;
+                    continue;
+                }
+            };
+
+            // send
+            for notification in notifications.iter_mut() {
+                self.render(notification, metadata.locale.as_deref());
+            }
+
+            let message = self.digest_email(email, &notifications, total);
+
+            if mailer.send(&message).await.is_err() {
+                report.failed += 1;
+                continue;
+            }
+
+            report.sent += 1;
+            report.notifications += total as u64;
+
+            if self
+                .auth
+                .update_user_metadata(user, |m| m.last_digest = Some(until))
+                .await
+                .is_err()
+            {
+                report.unrecorded += 1;
+            }
+        }
+
         // return
-        self.logs
-            .create_log(
-                String::from("notification"),
-                serde_json::to_string::<Notification>(&p).unwrap(),
+        Ok(report)
+    }
+
+    /// Render the digest [`Email`] of a user
+    ///
+    /// # Arguments:
+    /// * `to` - email address of the user
+    /// * `notifications` - the notifications listed in the digest
+    /// * `total` - total number of notifications in the digest (may be more than are listed)
+    fn digest_email(&self, to: String, notifications: &[Notification], total: usize) -> Email {
+        let heading = if total == 1 {
+            String::from("You have 1 unread notification.")
+        } else {
+            format!("You have {total} unread notifications.")
+        };
+
+        let mut text = format!("{heading}\n\n");
+        let mut html = format!("<p>{}</p>\n<ul>\n", escape_html(&heading));
+
+        for notification in notifications {
+            let address = if notification.address.starts_with('/') {
+                format!("{}{}", self.options.digest_base_url, notification.address)
+            } else {
+                notification.address.clone()
+            };
+
+            text.push_str(&format!(
+                "- [{}] {}\n  {address}\n",
+                notification.service, notification.content
+            ));
+
+            html.push_str(&format!(
+                "<li><b>{}</b>: <a href=\"{}\">{}</a></li>\n",
+                escape_html(&notification.service),
+                escape_html(&address),
+                escape_html(&notification.content)
+            ));
+        }
+
+        html.push_str("</ul>\n");
+
+        if total > notifications.len() {
+            let more = format!("And {} more.", total - notifications.len());
+            text.push_str(&format!("\n{more}\n"));
+            html.push_str(&format!("<p>{more}</p>\n"));
+        }
+
+        Email {
+            to,
+            subject: self.options.digest_subject.clone(),
+            text,
+            html,
+        }
+    }
+
+    /// Spawn a task on the current tokio runtime that calls [`NotificationDatabase::send_digests`]
+    /// every `interval`
+    ///
+    /// # Arguments:
+    /// * `mailer` - [`Mailer`] used to send the digests
+    /// * `interval` - time between digests
+    pub fn spawn_digester(
+        &self,
+        mailer: Arc<dyn Mailer>,
+        interval: std::time::Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        let db = self.clone();
+
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+
+            loop {
+                timer.tick().await;
+                let _ = db.send_digests(mailer.as_ref()).await;
+            }
+        })
+    }
+
+    /// Mark a [`Notification`] as read
+    ///
+    /// # Arguments:
+    /// * `user` - username of the notification's recipient
+    /// * `id` - `String` of the notification's id
+    pub async fn mark_read(&self, user: String, id: String) -> Result<()> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("UPDATE \"{}\" SET \"read_at\" = ? WHERE \"id\" = ? AND \"recipient\" = ? AND \"read_at\" IS NULL", self.options.table)
+        } else {
+            format!("UPDATE \"{}\" SET \"read_at\" = $1 WHERE \"id\" = $2 AND \"recipient\" = $3 AND \"read_at\" IS NULL", self.options.table)
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<i64>(utility::unix_epoch_timestamp() as i64)
+            .bind::<&String>(&id)
+            .bind::<&String>(&user)
+            .execute(c)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    // make sure the notification exists (it may already be read)
+                    self.get_notification(user, id).await?;
+                    return Ok(());
+                }
+
+                self.invalidate_unread_count(&user).await;
+                Ok(())
+            }
+            Err(_) => Err(NotificationError::Other),
+        }
+    }
+
+    /// Mark all of the given `user`'s [`Notification`]s as read
+    ///
+    /// Returns the number of notifications that were marked as read.
+    ///
+    /// # Arguments:
+    /// * `user` - username of the notifications' recipient
+    pub async fn mark_all_read(&self, user: String) -> Result<u64> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "UPDATE \"{}\" SET \"read_at\" = ? WHERE \"recipient\" = ? AND \"read_at\" IS NULL",
+                self.options.table
             )
+        } else {
+            format!("UPDATE \"{}\" SET \"read_at\" = $1 WHERE \"recipient\" = $2 AND \"read_at\" IS NULL", self.options.table)
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<i64>(utility::unix_epoch_timestamp() as i64)
+            .bind::<&String>(&user)
+            .execute(c)
             .await
+        {
+            Ok(r) => {
+                self.invalidate_unread_count(&user).await;
+                Ok(r.rows_affected())
+            }
+            Err(_) => Err(NotificationError::Other),
+        }
     }
+
+    /// Delete a [`Notification`]
+    ///
+    /// # Arguments:
+    /// * `user` - username of the notification's recipient
+    /// * `id` - `String` of the notification's id
+    pub async fn delete_notification(&self, user: String, id: String) -> Result<()> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"id\" = ? AND \"recipient\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"id\" = $1 AND \"recipient\" = $2",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<&String>(&id)
+            .bind::<&String>(&user)
+            .execute(c)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(NotificationError::NotFound);
+                }
+
+                // the deleted notification may have been unread
+                self.invalidate_unread_count(&user).await;
+                Ok(())
+            }
+            Err(_) => Err(NotificationError::Other),
+        }
+    }
+}
+
+/// Escape text for use in HTML
+fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
 }
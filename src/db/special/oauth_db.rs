@@ -0,0 +1,503 @@
+//! # OAuthDatabase
+//! External identity provider login (OAuth2 authorization code flow with PKCE)
+//!
+//! External identities are linked to users through the identities table
+//! (created by [`OAuthDatabase::init`]).
+use super::auth_db::{FullUser, UserMetadata};
+use crate::{utility, AuthDatabase, DefaultReturn, StarterDatabase};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// An external OAuth2 identity provider
+pub struct OAuthProvider {
+    /// Name used to identify the provider (ex: "guppy")
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// URL the user is sent to in order to authorize
+    pub authorize_url: String,
+    /// URL used to exchange the authorization code for an access token
+    pub token_url: String,
+    /// URL used to fetch the authorized user's information
+    pub userinfo_url: String,
+    /// URL the provider redirects back to (our callback)
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    /// Field of the userinfo response that uniquely identifies the user (ex: "sub")
+    pub subject_field: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, sqlx::FromRow)]
+/// An external identity, linked to a user if `username` is some
+pub struct ExternalIdentity {
+    pub provider: String,
+    /// The user's unique identifier at the provider
+    pub subject: String,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// The result of a completed OAuth2 login
+pub struct OAuthLogin {
+    pub identity: ExternalIdentity,
+    /// The linked user, none if the identity has not been linked yet
+    pub user: Option<FullUser<UserMetadata>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// A pending login, stored in the cache under its `state`
+struct PendingLogin {
+    provider: String,
+    verifier: String,
+    timestamp: u128,
+}
+
+/// How long a pending login stays valid (in milliseconds)
+const PENDING_LOGIN_TTL: u128 = 1000 * 60 * 10;
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+// ...
+/// OAuth database errors
+#[derive(Debug)]
+pub enum OAuthError {
+    ValueError,
+    NotFound,
+    InvalidState,
+    ProviderError,
+    Other,
+}
+
+impl OAuthError {
+    pub fn to_string(&self) -> String {
+        use OAuthError::*;
+        match self {
+            ValueError => String::from("One of the field values given is invalid."),
+            NotFound => String::from("No identity with this selector could be found."),
+            InvalidState => String::from("The login state is invalid or has expired."),
+            ProviderError => String::from("The identity provider returned an invalid response."),
+            _ => String::from("An unspecified error has occured"),
+        }
+    }
+}
+
+impl<T: Default> Into<DefaultReturn<T>> for OAuthError {
+    fn into(self) -> DefaultReturn<T> {
+        DefaultReturn {
+            success: false,
+            message: self.to_string(),
+            payload: T::default(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, OAuthError>;
+
+// ...
+#[derive(Clone)]
+pub struct DatabaseOptions {
+    /// The table to use for database operations
+    pub table: String,
+    /// The prefix used in redis keys
+    pub prefix: String,
+    /// Configured identity providers
+    pub providers: Vec<OAuthProvider>,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            table: String::from("Identities"),
+            prefix: String::from("oauth"),
+            providers: Vec::new(),
+        }
+    }
+}
+
+// database
+#[derive(Clone)]
+pub struct OAuthDatabase {
+    pub base: StarterDatabase,
+    pub auth: AuthDatabase,
+    pub options: DatabaseOptions,
+    pub http: reqwest::Client,
+}
+
+impl OAuthDatabase {
+    pub async fn new(
+        base: StarterDatabase,
+        auth: AuthDatabase,
+        options: DatabaseOptions,
+    ) -> OAuthDatabase {
+        OAuthDatabase {
+            base,
+            auth,
+            options,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Create the identities table if it doesn't already exist
+    pub async fn init(&self) -> bool {
+        let c = &self.base.db.client;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{}\" (
+                provider VARCHAR(255),
+                subject VARCHAR(255),
+                username VARCHAR(255),
+                timestamp BIGINT,
+                UNIQUE (provider, subject)
+            )",
+            self.options.table
+        ))
+        .execute(c)
+        .await
+        .is_ok()
+            && self
+                .base
+                .create_index(
+                    &self.options.table,
+                    &format!("{}_username", self.options.table),
+                    &["username"],
+                )
+                .await
+    }
+
+    /// Get a configured [`OAuthProvider`] by its `name`
+    ///
+    /// # Arguments:
+    /// * `name` - `String` of the provider's name
+    pub fn get_provider(&self, name: &str) -> Result<&OAuthProvider> {
+        match self.options.providers.iter().find(|p| p.name == name) {
+            Some(p) => Ok(p),
+            None => Err(OAuthError::NotFound),
+        }
+    }
+
+    // login
+
+    /// Begin a login with the given provider
+    ///
+    /// Returns the URL the user should be redirected to. The generated `state` and
+    /// PKCE verifier are kept in the cache until [`OAuthDatabase::finish_login`] (or
+    /// until they expire).
+    ///
+    /// # Arguments:
+    /// * `provider` - `String` of the provider's name
+    pub async fn begin_login(&self, provider: String) -> Result<String> {
+        let p = self.get_provider(&provider)?;
+
+        // create pending login
+        let state = utility::random_id();
+        let verifier = format!("{}{}", utility::random_id(), utility::random_id());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        let url = match reqwest::Url::parse_with_params(
+            &p.authorize_url,
+            &[
+                ("response_type", "code"),
+                ("client_id", &p.client_id),
+                ("redirect_uri", &p.redirect_url),
+                ("scope", &p.scopes.join(" ")),
+                ("state", &state),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        ) {
+            Ok(u) => u,
+            Err(_) => return Err(OAuthError::ValueError),
+        };
+
+        if !self
+            .base
+            .cachedb
+            .set_expiring(
+                format!("{}:state:{}", self.options.prefix, state),
+                serde_json::to_string::<PendingLogin>(&PendingLogin {
+                    provider,
+                    verifier,
+                    timestamp: utility::unix_epoch_timestamp(),
+                })
+                .unwrap(),
+                (PENDING_LOGIN_TTL / 1000) as u64,
+            )
+            .await
+        {
+            return Err(OAuthError::Other);
+        }
+
+        // return
+        Ok(url.to_string())
+    }
+
+    /// Finish a login after the provider redirected back to us
+    ///
+    /// Verifies the `state`, exchanges the `code` for an access token and fetches
+    /// the user's subject from the provider.
+    ///
+    /// # Arguments:
+    /// * `code` - `String` of the authorization code
+    /// * `state` - `String` of the state returned by the provider
+    pub async fn finish_login(&self, code: String, state: String) -> Result<OAuthLogin> {
+        // verify state (states can only be used once)
+        let key = format!("{}:state:{}", self.options.prefix, state);
+        let pending = match self.base.cachedb.take(key).await {
+            Some(p) => match serde_json::from_str::<PendingLogin>(&p) {
+                Ok(p) => p,
+                Err(_) => return Err(OAuthError::InvalidState),
+            },
+            None => return Err(OAuthError::InvalidState),
+        };
+
+        if utility::unix_epoch_timestamp().saturating_sub(pending.timestamp) > PENDING_LOGIN_TTL {
+            return Err(OAuthError::InvalidState);
+        }
+
+        let p = self.get_provider(&pending.provider)?;
+
+        // exchange code
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code.as_str()),
+            ("redirect_uri", p.redirect_url.as_str()),
+            ("client_id", p.client_id.as_str()),
+            ("code_verifier", pending.verifier.as_str()),
+        ];
+
+        if let Some(ref secret) = p.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let token = match self
+            .http
+            .post(&p.token_url)
+            .header("Accept", "application/json")
+            .form(&form)
+            .send()
+            .await
+        {
+            Ok(r) => match r.error_for_status() {
+                Ok(r) => match r.json::<TokenResponse>().await {
+                    Ok(t) => t,
+                    Err(_) => return Err(OAuthError::ProviderError),
+                },
+                Err(_) => return Err(OAuthError::ProviderError),
+            },
+            Err(_) => return Err(OAuthError::ProviderError),
+        };
+
+        // fetch subject
+        let info = match self
+            .http
+            .get(&p.userinfo_url)
+            .bearer_auth(token.access_token)
+            .header("Accept", "application/json")
+            .send()
+            .await
+        {
+            Ok(r) => match r.error_for_status() {
+                Ok(r) => match r.json::<serde_json::Value>().await {
+                    Ok(i) => i,
+                    Err(_) => return Err(OAuthError::ProviderError),
+                },
+                Err(_) => return Err(OAuthError::ProviderError),
+            },
+            Err(_) => return Err(OAuthError::ProviderError),
+        };
+
+        let subject = match info.get(&p.subject_field) {
+            Some(serde_json::Value::String(s)) => s.to_owned(),
+            Some(serde_json::Value::Number(n)) => n.to_string(),
+            _ => return Err(OAuthError::ProviderError),
+        };
+
+        // get linked user
+        let identity = match self
+            .get_identity(pending.provider.clone(), subject.clone())
+            .await
+        {
+            Ok(i) => i,
+            Err(OAuthError::NotFound) => ExternalIdentity {
+                provider: pending.provider,
+                subject,
+                username: None,
+            },
+            Err(e) => return Err(e),
+        };
+
+        let user = match identity.username {
+            Some(ref username) => match self.auth.get_user_by_username(username.clone()).await {
+                Ok(u) => Some(u),
+                Err(_) => return Err(OAuthError::NotFound),
+            },
+            None => None,
+        };
+
+        // return
+        Ok(OAuthLogin { identity, user })
+    }
+
+    // identities
+
+    // GET
+    /// Get the [`ExternalIdentity`] linked to the given provider `subject`
+    ///
+    /// # Arguments:
+    /// * `provider` - `String` of the provider's name
+    /// * `subject` - `String` of the user's identifier at the provider
+    pub async fn get_identity(
+        &self,
+        provider: String,
+        subject: String,
+    ) -> Result<ExternalIdentity> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"provider\" = ? AND \"subject\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"provider\" = $1 AND \"subject\" = $2",
+                self.options.table
+            )
+        };
+
+        match self
+            .base
+            .fetch_one_as::<ExternalIdentity>(
+                sqlx::query(&query)
+                    .bind::<&String>(&provider)
+                    .bind::<&String>(&subject),
+            )
+            .await
+        {
+            Ok(i) => Ok(i),
+            Err(_) => Err(OAuthError::NotFound),
+        }
+    }
+
+    /// Get all [`ExternalIdentity`]s linked to the given `username`
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    pub async fn get_user_identities(&self, username: String) -> Result<Vec<ExternalIdentity>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"username\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"username\" = $1",
+                self.options.table
+            )
+        };
+
+        match self
+            .base
+            .fetch_all_as::<ExternalIdentity>(sqlx::query(&query).bind::<&String>(&username))
+            .await
+        {
+            Ok(i) => Ok(i),
+            Err(_) => Err(OAuthError::Other),
+        }
+    }
+
+    // SET
+    /// Link an external identity to a user
+    ///
+    /// # Arguments:
+    /// * `provider` - `String` of the provider's name
+    /// * `subject` - `String` of the user's identifier at the provider
+    /// * `username` - `String` of the user's username
+    pub async fn link_identity(
+        &self,
+        provider: String,
+        subject: String,
+        username: String,
+    ) -> Result<()> {
+        // make sure provider and user exist
+        self.get_provider(&provider)?;
+
+        if self
+            .auth
+            .get_user_by_username(username.clone())
+            .await
+            .is_err()
+        {
+            return Err(OAuthError::NotFound);
+        }
+
+        // make sure identity isn't already linked
+        if self
+            .get_identity(provider.clone(), subject.clone())
+            .await
+            .is_ok()
+        {
+            return Err(OAuthError::ValueError);
+        }
+
+        // ...
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("INSERT INTO \"{}\" VALUES (?, ?, ?, ?)", self.options.table)
+        } else {
+            format!(
+                "INSERT INTO \"{}\" VALUES ($1, $2, $3, $4)",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<&String>(&provider)
+            .bind::<&String>(&subject)
+            .bind::<&String>(&username)
+            .bind::<i64>(utility::unix_epoch_timestamp() as i64)
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(OAuthError::Other),
+        }
+    }
+
+    /// Unlink an external identity
+    ///
+    /// # Arguments:
+    /// * `provider` - `String` of the provider's name
+    /// * `subject` - `String` of the user's identifier at the provider
+    pub async fn unlink_identity(&self, provider: String, subject: String) -> Result<()> {
+        // make sure identity exists
+        self.get_identity(provider.clone(), subject.clone()).await?;
+
+        // ...
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"provider\" = ? AND \"subject\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"provider\" = $1 AND \"subject\" = $2",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<&String>(&provider)
+            .bind::<&String>(&subject)
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(OAuthError::Other),
+        }
+    }
+}
@@ -0,0 +1,969 @@
+//! # WebhookDatabase
+//! Outbound webhook delivery
+//!
+//! Users and services register endpoints for the events they want to receive. Every
+//! delivery is a `POST` of a JSON [`WebhookPayload`], signed with the endpoint's secret:
+//!
+//! * `X-Webhook-Id` - id of the delivery (the same for every attempt)
+//! * `X-Webhook-Event` - name of the event
+//! * `X-Webhook-Timestamp` - time the attempt was sent at (seconds since epoch)
+//! * `X-Webhook-Signature` - `sha256=` followed by the hex HMAC-SHA256 of `{timestamp}.{body}`
+//!
+//! Deliveries are queued in the database, so they survive restarts. Failed deliveries
+//! are retried with exponential backoff by [`WebhookDatabase::run_worker`], and every
+//! attempt is recorded in the logs table as a [`WebhookDeliveryLog`].
+//!
+//! Endpoints can't be on loopback, private or link-local addresses (unless
+//! [`DatabaseOptions::allow_private_addresses`] is set), and redirects aren't followed.
+use super::log_db::{LogKind, LogQuery, TypedLogPage};
+use crate::{token, utility, DefaultReturn, LogDatabase, StarterDatabase};
+
+use hex_fmt::HexFmt;
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::Row;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A registered webhook endpoint
+pub struct Webhook {
+    pub id: String,
+    /// Username of the webhook's owner (or [`service_owner`] of a service)
+    pub owner: String,
+    pub url: String,
+    /// Secret used to sign deliveries
+    pub secret: String,
+    /// Names of the events sent to the webhook (all events if empty)
+    pub events: Vec<String>,
+    // dates
+    pub timestamp: u128,
+}
+
+impl<'r, R> sqlx::FromRow<'r, R> for Webhook
+where
+    R: Row,
+    &'r str: sqlx::ColumnIndex<R>,
+    String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> sqlx::Result<Self> {
+        let events = row.try_get::<String, _>("events")?;
+
+        Ok(Webhook {
+            id: row.try_get("id")?,
+            owner: row.try_get("owner")?,
+            url: row.try_get("url")?,
+            secret: row.try_get("secret")?,
+            events: match serde_json::from_str(&events) {
+                Ok(e) => e,
+                Err(e) => {
+                    return Err(sqlx::Error::ColumnDecode {
+                        index: String::from("events"),
+                        source: Box::new(e),
+                    })
+                }
+            },
+            timestamp: row.try_get::<i64, _>("timestamp")? as u128,
+        })
+    }
+}
+
+impl Webhook {
+    /// Check if the given event is sent to this webhook
+    pub fn accepts(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// The body of a webhook delivery
+pub struct WebhookPayload<T> {
+    /// Id of the delivery
+    pub id: String,
+    /// Name of the event (ex: "notification.created")
+    pub event: String,
+    /// Time the event happened at
+    pub timestamp: u128,
+    pub data: T,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A single delivery attempt
+pub struct WebhookDelivery {
+    /// Id of the delivery (the same for every attempt)
+    pub id: String,
+    /// Id of the [`Webhook`] the delivery was sent to
+    pub webhook: String,
+    pub event: String,
+    /// Attempt number, starting at 1
+    pub attempt: u32,
+    /// HTTP status returned by the endpoint, none if the request failed
+    pub status: Option<u16>,
+    /// Error of the request, if it failed
+    pub error: Option<String>,
+    pub success: bool,
+}
+
+/// [`LogKind`] of [`WebhookDelivery`] attempts
+pub struct WebhookDeliveryLog;
+
+impl LogKind for WebhookDeliveryLog {
+    const LOGTYPE: &'static str = "webhook_delivery";
+    type Payload = WebhookDelivery;
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A delivery waiting in the queue
+pub struct PendingDelivery {
+    /// Id of the delivery
+    pub id: String,
+    /// Id of the [`Webhook`] the delivery is sent to
+    pub webhook: String,
+    pub event: String,
+    pub body: String,
+    /// Number of attempts made so far
+    pub attempt: u32,
+    /// Time the next attempt can be made at
+    pub next_attempt: u128,
+}
+
+impl<'r, R> sqlx::FromRow<'r, R> for PendingDelivery
+where
+    R: Row,
+    &'r str: sqlx::ColumnIndex<R>,
+    String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> sqlx::Result<Self> {
+        Ok(PendingDelivery {
+            id: row.try_get("id")?,
+            webhook: row.try_get("webhook")?,
+            event: row.try_get("event")?,
+            body: row.try_get("body")?,
+            attempt: row.try_get::<i64, _>("attempt")? as u32,
+            next_attempt: row.try_get::<i64, _>("next_attempt")? as u128,
+        })
+    }
+}
+
+/// Get the owner of a service's webhooks
+///
+/// Services own webhooks as `service:{name}`, usernames can't contain `:` so they never
+/// share an owner with a service.
+///
+/// # Arguments:
+/// * `service` - name of the service
+pub fn service_owner(service: &str) -> String {
+    format!("service:{service}")
+}
+
+/// Check if an address is publicly routable (not loopback, private, link-local, etc.)
+///
+/// # Arguments:
+/// * `ip` - [`IpAddr`]
+pub fn is_public_address(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_unspecified()
+                | ip.is_loopback()
+                | ip.is_private()
+                | ip.is_link_local()
+                | ip.is_broadcast()
+                | ip.is_documentation()
+                | ip.is_multicast()
+                | (a == 0)
+                // shared address space (100.64.0.0/10)
+                | ((a == 100) && ((b & 0xc0) == 64))
+                // reserved (240.0.0.0/4)
+                | (a >= 240))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_address(&IpAddr::V4(ip));
+            }
+
+            let first = ip.segments()[0];
+
+            !(ip.is_unspecified()
+                | ip.is_loopback()
+                | ip.is_multicast()
+                // unique local (fc00::/7)
+                | ((first & 0xfe00) == 0xfc00)
+                // link-local (fe80::/10)
+                | ((first & 0xffc0) == 0xfe80))
+        }
+    }
+}
+
+/// DNS resolver that only returns public addresses (see [`is_public_address`])
+///
+/// Used by the HTTP client, so endpoints can't resolve to internal addresses after
+/// they were checked.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|a| is_public_address(&a.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err("host doesn't resolve to a public address".into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Sign a delivery body
+///
+/// # Arguments:
+/// * `secret` - the webhook's secret
+/// * `timestamp` - time the delivery is sent at (seconds since epoch)
+/// * `body` - the delivery's body
+pub fn sign_payload(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    format!("sha256={}", HexFmt(mac.finalize().into_bytes()))
+}
+
+/// Verify the signature of a received delivery
+///
+/// Deliveries sent more than `tolerance` seconds ago (or ahead) are rejected, so captured
+/// deliveries can't be replayed later.
+///
+/// # Arguments:
+/// * `secret` - the webhook's secret
+/// * `timestamp` - value of the `X-Webhook-Timestamp` header
+/// * `body` - the delivery's body
+/// * `signature` - value of the `X-Webhook-Signature` header
+/// * `tolerance` - maximum difference (in seconds) between `timestamp` and now, ex: 300
+pub fn verify_payload(
+    secret: &str,
+    timestamp: u64,
+    body: &str,
+    signature: &str,
+    tolerance: u64,
+) -> bool {
+    if token::now().abs_diff(timestamp) > tolerance {
+        return false;
+    }
+
+    let expected = sign_payload(secret, timestamp, body);
+
+    // constant time comparison
+    (expected.len() == signature.len())
+        && expected
+            .bytes()
+            .zip(signature.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+// ...
+/// Webhook database errors
+#[derive(Debug)]
+pub enum WebhookError {
+    ValueError,
+    NotFound,
+    Other,
+}
+
+impl WebhookError {
+    pub fn to_string(&self) -> String {
+        use WebhookError::*;
+        match self {
+            ValueError => String::from("One of the field values given is invalid."),
+            NotFound => String::from("No webhook with this selector could be found."),
+            _ => String::from("An unspecified error has occured"),
+        }
+    }
+}
+
+impl<T: Default> Into<DefaultReturn<T>> for WebhookError {
+    fn into(self) -> DefaultReturn<T> {
+        DefaultReturn {
+            success: false,
+            message: self.to_string(),
+            payload: T::default(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, WebhookError>;
+
+// ...
+#[derive(Clone)]
+pub struct DatabaseOptions {
+    /// The table to use for database operations
+    pub table: String,
+    /// The table pending deliveries are queued in
+    pub queue_table: String,
+    /// Maximum number of attempts for each delivery
+    pub max_attempts: u32,
+    /// Time waited before the first retry, doubled after every failed attempt
+    pub backoff: Duration,
+    /// Time an attempt can take before it fails
+    pub timeout: Duration,
+    /// How often [`WebhookDatabase::run_worker`] checks the queue
+    pub poll_interval: Duration,
+    /// Allow endpoints on loopback, private and link-local addresses (for development)
+    pub allow_private_addresses: bool,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        Self {
+            table: String::from("Webhooks"),
+            queue_table: String::from("WebhookQueue"),
+            max_attempts: 5,
+            backoff: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+            poll_interval: Duration::from_secs(1),
+            allow_private_addresses: false,
+        }
+    }
+}
+
+// database
+#[derive(Clone)]
+pub struct WebhookDatabase {
+    pub base: StarterDatabase,
+    pub logs: LogDatabase,
+    pub options: DatabaseOptions,
+    pub http: reqwest::Client,
+}
+
+impl WebhookDatabase {
+    pub async fn new(
+        base: StarterDatabase,
+        logs: LogDatabase,
+        options: DatabaseOptions,
+    ) -> WebhookDatabase {
+        let mut http = reqwest::Client::builder()
+            .timeout(options.timeout)
+            .redirect(reqwest::redirect::Policy::none());
+
+        if !options.allow_private_addresses {
+            http = http.dns_resolver(Arc::new(PublicResolver));
+        }
+
+        WebhookDatabase {
+            base,
+            logs,
+            http: http.build().unwrap(),
+            options,
+        }
+    }
+
+    /// Create the webhooks and queue tables if they don't already exist
+    pub async fn init(&self) -> bool {
+        let c = &self.base.db.client;
+        let queue = &self.options.queue_table;
+
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS \"{}\" (
+                id VARCHAR(255) PRIMARY KEY,
+                owner VARCHAR(255),
+                url TEXT,
+                secret VARCHAR(255),
+                events TEXT,
+                timestamp BIGINT
+            )",
+            self.options.table
+        ))
+        .execute(c)
+        .await
+        .is_ok()
+            && sqlx::query(&format!(
+                "CREATE TABLE IF NOT EXISTS \"{queue}\" (
+                    id VARCHAR(255) PRIMARY KEY,
+                    webhook VARCHAR(255),
+                    event VARCHAR(255),
+                    body TEXT,
+                    attempt BIGINT,
+                    next_attempt BIGINT
+                )"
+            ))
+            .execute(c)
+            .await
+            .is_ok()
+            && self
+                .base
+                .create_index(
+                    &self.options.table,
+                    &format!("{}_owner", self.options.table),
+                    &["owner"],
+                )
+                .await
+            && self
+                .base
+                .create_index(queue, &format!("{queue}_next_attempt"), &["next_attempt"])
+                .await
+    }
+
+    /// Check if a [`Webhook`] can be sent to the given URL
+    ///
+    /// The URL must be http or https, and its host must only resolve to public
+    /// addresses (unless [`DatabaseOptions::allow_private_addresses`] is set).
+    ///
+    /// # Arguments:
+    /// * `url` - `&str` of the endpoint's URL
+    pub async fn check_url(&self, url: &str) -> Result<()> {
+        let url = match reqwest::Url::parse(url) {
+            Ok(u) => u,
+            Err(_) => return Err(WebhookError::ValueError),
+        };
+
+        if (url.scheme() != "http") && (url.scheme() != "https") {
+            return Err(WebhookError::ValueError);
+        }
+
+        if self.options.allow_private_addresses {
+            return Ok(());
+        }
+
+        let (host, port) = match (url.host_str(), url.port_or_known_default()) {
+            (Some(h), Some(p)) => (h.trim_start_matches('[').trim_end_matches(']'), p),
+            _ => return Err(WebhookError::ValueError),
+        };
+
+        let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => match tokio::net::lookup_host((host, port)).await {
+                Ok(a) => a.collect(),
+                Err(_) => return Err(WebhookError::ValueError),
+            },
+        };
+
+        if addrs.is_empty() || !addrs.iter().all(|a| is_public_address(&a.ip())) {
+            return Err(WebhookError::ValueError);
+        }
+
+        Ok(())
+    }
+
+    // webhooks
+
+    // GET
+    /// Get a [`Webhook`] by its `id`
+    ///
+    /// # Arguments:
+    /// * `id` - `String` of the webhook's id
+    pub async fn get_webhook(&self, id: String) -> Result<Webhook> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!("SELECT * FROM \"{}\" WHERE \"id\" = ?", self.options.table)
+        } else {
+            format!("SELECT * FROM \"{}\" WHERE \"id\" = $1", self.options.table)
+        };
+
+        match self
+            .base
+            .fetch_one_as::<Webhook>(sqlx::query(&query).bind::<&String>(&id))
+            .await
+        {
+            Ok(w) => Ok(w),
+            Err(_) => Err(WebhookError::NotFound),
+        }
+    }
+
+    /// Get all [`Webhook`]s owned by the given `owner`
+    ///
+    /// # Arguments:
+    /// * `owner` - `String` of the owner's username (or [`service_owner`] of a service)
+    pub async fn get_webhooks_by_owner(&self, owner: String) -> Result<Vec<Webhook>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"owner\" = ? ORDER BY \"timestamp\" DESC",
+                self.options.table
+            )
+        } else {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"owner\" = $1 ORDER BY \"timestamp\" DESC",
+                self.options.table
+            )
+        };
+
+        match self
+            .base
+            .fetch_all_as::<Webhook>(sqlx::query(&query).bind::<&String>(&owner))
+            .await
+        {
+            Ok(w) => Ok(w),
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    /// Get a page of the delivery attempts of a [`Webhook`], newest first
+    ///
+    /// # Arguments:
+    /// * `id` - `String` of the webhook's id
+    /// * `cursor` - cursor of the page (from a previous page's `next`)
+    pub async fn get_webhook_deliveries(
+        &self,
+        id: String,
+        cursor: Option<String>,
+    ) -> Result<TypedLogPage<WebhookDelivery>> {
+        match self
+            .logs
+            .list_typed::<WebhookDeliveryLog>(
+                LogQuery::new()
+                    .content(&format!(
+                        "\"webhook\":{}",
+                        serde_json::to_string(&id).unwrap()
+                    ))
+                    .cursor(cursor),
+            )
+            .await
+        {
+            Ok(p) => Ok(p),
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    // SET
+    /// Register a new [`Webhook`]
+    ///
+    /// Returns the webhook, including the generated secret.
+    ///
+    /// # Arguments:
+    /// * `owner` - `String` of the owner's username (or [`service_owner`] of a service)
+    /// * `url` - `String` of the endpoint's URL (see [`WebhookDatabase::check_url`])
+    /// * `events` - names of the events sent to the webhook (all events if empty)
+    pub async fn create_webhook(
+        &self,
+        owner: String,
+        url: String,
+        events: Vec<String>,
+    ) -> Result<Webhook> {
+        // check url
+        self.check_url(&url).await?;
+
+        // ...
+        let webhook = Webhook {
+            id: utility::random_id(),
+            owner,
+            url,
+            secret: utility::random_id(),
+            events,
+            timestamp: utility::unix_epoch_timestamp(),
+        };
+
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "INSERT INTO \"{}\" VALUES (?, ?, ?, ?, ?, ?)",
+                self.options.table
+            )
+        } else {
+            format!(
+                "INSERT INTO \"{}\" VALUES ($1, $2, $3, $4, $5, $6)",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<&String>(&webhook.id)
+            .bind::<&String>(&webhook.owner)
+            .bind::<&String>(&webhook.url)
+            .bind::<&String>(&webhook.secret)
+            .bind::<String>(serde_json::to_string(&webhook.events).unwrap())
+            .bind::<i64>(webhook.timestamp as i64)
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(webhook),
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    /// Delete a [`Webhook`]
+    ///
+    /// # Arguments:
+    /// * `owner` - `String` of the owner's username (or [`service_owner`] of a service)
+    /// * `id` - `String` of the webhook's id
+    pub async fn delete_webhook(&self, owner: String, id: String) -> Result<()> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"id\" = ? AND \"owner\" = ?",
+                self.options.table
+            )
+        } else {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"id\" = $1 AND \"owner\" = $2",
+                self.options.table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<&String>(&id)
+            .bind::<&String>(&owner)
+            .execute(c)
+            .await
+        {
+            Ok(r) => {
+                if r.rows_affected() == 0 {
+                    return Err(WebhookError::NotFound);
+                }
+
+                Ok(())
+            }
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    // deliveries
+
+    /// Send an event to the webhooks of the given owners that accept it
+    ///
+    /// Deliveries are queued, then sent in the background on the current tokio runtime.
+    /// Failed deliveries are retried by [`WebhookDatabase::run_worker`]. Returns the ids
+    /// of the deliveries.
+    ///
+    /// # Arguments:
+    /// * `owners` - usernames of the owners (or [`service_owner`] of services)
+    /// * `event` - name of the event (ex: "notification.created")
+    /// * `data` - data of the event
+    pub async fn dispatch<T: Serialize>(
+        &self,
+        owners: Vec<String>,
+        event: &str,
+        data: &T,
+    ) -> Result<Vec<String>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "INSERT INTO \"{}\" VALUES (?, ?, ?, ?, ?, ?)",
+                self.options.queue_table
+            )
+        } else {
+            format!(
+                "INSERT INTO \"{}\" VALUES ($1, $2, $3, $4, $5, $6)",
+                self.options.queue_table
+            )
+        };
+
+        let mut queued: Vec<(WebhookPayload<&T>, String, String)> = Vec::new();
+
+        for owner in owners {
+            for webhook in self.get_webhooks_by_owner(owner).await? {
+                if !webhook.accepts(event) {
+                    continue;
+                }
+
+                let payload = WebhookPayload {
+                    id: utility::random_id(),
+                    event: event.to_string(),
+                    timestamp: utility::unix_epoch_timestamp(),
+                    data,
+                };
+
+                let body = match serde_json::to_string(&payload) {
+                    Ok(b) => b,
+                    Err(_) => return Err(WebhookError::ValueError),
+                };
+
+                queued.push((payload, webhook.id, body));
+            }
+        }
+
+        // queue (every delivery or none of them)
+        let c = &self.base.db.client;
+        let mut transaction = match c.begin().await {
+            Ok(t) => t,
+            Err(_) => return Err(WebhookError::Other),
+        };
+
+        for (payload, webhook, body) in &queued {
+            if sqlx::query(&query)
+                .bind::<&String>(&payload.id)
+                .bind::<&String>(webhook)
+                .bind::<&String>(&payload.event)
+                .bind::<&String>(body)
+                .bind::<i64>(0)
+                .bind::<i64>(payload.timestamp as i64)
+                .execute(&mut *transaction)
+                .await
+                .is_err()
+            {
+                return Err(WebhookError::Other);
+            }
+        }
+
+        if transaction.commit().await.is_err() {
+            return Err(WebhookError::Other);
+        }
+
+        let ids: Vec<String> = queued.into_iter().map(|(p, _, _)| p.id).collect();
+
+        // send
+        if !ids.is_empty() {
+            let db = self.clone();
+            tokio::spawn(async move {
+                let _ = db.deliver_pending().await;
+            });
+        }
+
+        // return
+        Ok(ids)
+    }
+
+    /// Get the queued deliveries that can be attempted now
+    async fn get_due_deliveries(&self) -> Result<Vec<PendingDelivery>> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"next_attempt\" <= ? ORDER BY \"next_attempt\" ASC LIMIT 100",
+                self.options.queue_table
+            )
+        } else {
+            format!(
+                "SELECT * FROM \"{}\" WHERE \"next_attempt\" <= $1 ORDER BY \"next_attempt\" ASC LIMIT 100",
+                self.options.queue_table
+            )
+        };
+
+        match self
+            .base
+            .fetch_all_as::<PendingDelivery>(
+                sqlx::query(&query).bind::<i64>(utility::unix_epoch_timestamp() as i64),
+            )
+            .await
+        {
+            Ok(d) => Ok(d),
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    /// Claim a queued delivery, so no other worker attempts it at the same time
+    ///
+    /// The claim expires if the delivery isn't updated in time (ex: the server stopped).
+    /// Returns false if the delivery was claimed by another worker.
+    async fn claim_delivery(&self, delivery: &PendingDelivery) -> Result<bool> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "UPDATE \"{}\" SET \"next_attempt\" = ? WHERE \"id\" = ? AND \"next_attempt\" = ?",
+                self.options.queue_table
+            )
+        } else {
+            format!(
+                "UPDATE \"{}\" SET \"next_attempt\" = $1 WHERE \"id\" = $2 AND \"next_attempt\" = $3",
+                self.options.queue_table
+            )
+        };
+
+        let expires = utility::unix_epoch_timestamp() + self.options.timeout.as_millis() * 2;
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<i64>(expires as i64)
+            .bind::<&String>(&delivery.id)
+            .bind::<i64>(delivery.next_attempt as i64)
+            .execute(c)
+            .await
+        {
+            Ok(r) => Ok(r.rows_affected() == 1),
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    /// Remove a delivery from the queue
+    async fn dequeue_delivery(&self, id: &String) -> Result<()> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"id\" = ?",
+                self.options.queue_table
+            )
+        } else {
+            format!(
+                "DELETE FROM \"{}\" WHERE \"id\" = $1",
+                self.options.queue_table
+            )
+        };
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query).bind::<&String>(id).execute(c).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    /// Schedule the next attempt of a delivery
+    async fn reschedule_delivery(&self, id: &String, attempt: u32) -> Result<()> {
+        let query: String = if (self.base.db._type == "sqlite") | (self.base.db._type == "mysql") {
+            format!(
+                "UPDATE \"{}\" SET \"attempt\" = ?, \"next_attempt\" = ? WHERE \"id\" = ?",
+                self.options.queue_table
+            )
+        } else {
+            format!(
+                "UPDATE \"{}\" SET \"attempt\" = $1, \"next_attempt\" = $2 WHERE \"id\" = $3",
+                self.options.queue_table
+            )
+        };
+
+        // the delay doubles after every failed attempt
+        let delay = self.options.backoff.as_millis() << (attempt - 1).min(32);
+        let next_attempt = utility::unix_epoch_timestamp() + delay;
+
+        let c = &self.base.db.client;
+        match sqlx::query(&query)
+            .bind::<i64>(attempt as i64)
+            .bind::<i64>(next_attempt as i64)
+            .bind::<&String>(id)
+            .execute(c)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(_) => Err(WebhookError::Other),
+        }
+    }
+
+    /// Attempt every queued delivery that is due
+    ///
+    /// Successful deliveries, and deliveries that reached [`DatabaseOptions::max_attempts`],
+    /// are removed from the queue. Returns the number of attempts made.
+    pub async fn deliver_pending(&self) -> Result<u32> {
+        let mut attempts: u32 = 0;
+
+        for pending in self.get_due_deliveries().await? {
+            if !self.claim_delivery(&pending).await? {
+                continue;
+            }
+
+            // the webhook may have been deleted
+            let webhook = match self.get_webhook(pending.webhook.clone()).await {
+                Ok(w) => w,
+                Err(WebhookError::NotFound) => {
+                    self.dequeue_delivery(&pending.id).await?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let delivery = self
+                .attempt(
+                    &webhook,
+                    pending.id.clone(),
+                    pending.event,
+                    pending.body,
+                    pending.attempt + 1,
+                )
+                .await;
+
+            attempts += 1;
+
+            if delivery.success || (delivery.attempt >= self.options.max_attempts) {
+                self.dequeue_delivery(&pending.id).await?;
+            } else {
+                self.reschedule_delivery(&pending.id, delivery.attempt)
+                    .await?;
+            }
+        }
+
+        // return
+        Ok(attempts)
+    }
+
+    /// Attempt queued deliveries every [`DatabaseOptions::poll_interval`], forever
+    ///
+    /// Should be spawned once per server (more workers don't send deliveries twice).
+    pub async fn run_worker(&self) {
+        loop {
+            let _ = self.deliver_pending().await;
+            tokio::time::sleep(self.options.poll_interval).await;
+        }
+    }
+
+    /// Make a single delivery attempt to a [`Webhook`], and record it
+    ///
+    /// # Arguments:
+    /// * `webhook` - [`Webhook`]
+    /// * `id` - `String` of the delivery's id
+    /// * `event` - `String` of the event's name
+    /// * `body` - `String` of the delivery's body
+    /// * `attempt` - attempt number, starting at 1
+    pub async fn attempt(
+        &self,
+        webhook: &Webhook,
+        id: String,
+        event: String,
+        body: String,
+        attempt: u32,
+    ) -> WebhookDelivery {
+        // the address may not be allowed anymore (ex: the options changed)
+        if self.check_url(&webhook.url).await.is_err() {
+            let delivery = WebhookDelivery {
+                id,
+                webhook: webhook.id.clone(),
+                event,
+                attempt,
+                status: None,
+                error: Some(String::from("Endpoint address is not allowed.")),
+                success: false,
+            };
+
+            let _ = self
+                .logs
+                .create_typed::<WebhookDeliveryLog>(&delivery)
+                .await;
+
+            return delivery;
+        }
+
+        // send
+        let timestamp = token::now();
+        let res = self
+            .http
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Id", &id)
+            .header("X-Webhook-Event", &event)
+            .header("X-Webhook-Timestamp", timestamp.to_string())
+            .header(
+                "X-Webhook-Signature",
+                sign_payload(&webhook.secret, timestamp, &body),
+            )
+            .body(body)
+            .send()
+            .await;
+
+        let delivery = match res {
+            Ok(r) => WebhookDelivery {
+                id,
+                webhook: webhook.id.clone(),
+                event,
+                attempt,
+                status: Some(r.status().as_u16()),
+                error: None,
+                success: r.status().is_success(),
+            },
+            Err(e) => WebhookDelivery {
+                id,
+                webhook: webhook.id.clone(),
+                event,
+                attempt,
+                status: None,
+                error: Some(e.to_string()),
+                success: false,
+            },
+        };
+
+        // record attempt
+        let _ = self
+            .logs
+            .create_typed::<WebhookDeliveryLog>(&delivery)
+            .await;
+
+        delivery
+    }
+}
@@ -7,15 +7,47 @@
 
 pub mod config;
 pub mod db;
+#[cfg(feature = "tracing")]
+pub mod log_sink;
+pub mod mail;
+#[cfg(feature = "actix")]
+pub mod sse;
+pub mod token;
 pub mod utility;
 
 // databases
 pub use db::cachedb::CacheDB;
-pub use db::db::{DefaultReturn, StarterDatabase};
-pub use db::special::auth_db::AuthDatabase;
-pub use db::special::log_db::LogDatabase;
-pub use db::special::notification_db::{Notification, NotificationDatabase};
+pub use db::db::{DefaultReturn, Driver, DriverRow, StarterDatabase};
+pub use db::pubsub::PubSub;
+pub use db::special::auth_db::{
+    AuditAction, AuditContext, AuditEntry, AuthDatabase, UserDataExport,
+};
+pub use db::special::invite_db::{Invite, InviteDatabase};
+pub use db::special::log_db::{
+    ImportMode, ImportReport, LogDatabase, LogFilter, LogKind, LogOrder, LogPage, LogQuery,
+    PruneReport, RetentionPolicy, TypedLog,
+};
+pub use db::special::notification_db::{
+    DigestReport, Notification, NotificationDatabase, NotificationPreferences,
+    NotificationTemplate, QuietHours,
+};
 pub use db::sql::DatabaseOpts;
+pub use mail::{Email, FileMailer, Mailer, MemoryMailer};
+
+#[cfg(feature = "smtp")]
+pub use mail::SmtpMailer;
+
+#[cfg(feature = "oauth")]
+pub use db::special::oauth_db::{OAuthDatabase, OAuthProvider};
+
+#[cfg(feature = "webhooks")]
+pub use db::special::webhook_db::{Webhook, WebhookDatabase, WebhookDelivery, WebhookPayload};
+
+#[cfg(feature = "tracing")]
+pub use log_sink::{LogSink, LogSinkOptions};
+
+#[cfg(feature = "actix")]
+pub use sse::SseOptions;
 
 pub use sqlx::query;
 
@@ -0,0 +1,295 @@
+//! # LogSink
+//! Application logging backed by [`LogDatabase`]
+//!
+//! [`LogSink`] is both a [`tracing_subscriber::Layer`] and a [`log::Log`] backend.
+//! Events are queued in a bounded channel and written in batches by a background
+//! worker, so logging never waits on the database. When the queue is full, or a
+//! batch can't be written, events are dropped and counted instead.
+//!
+//! ```ignore
+//! use tracing_subscriber::prelude::*;
+//!
+//! let (sink, worker) = LogSink::new(logs, LogSinkOptions::default());
+//! tokio::spawn(worker);
+//!
+//! tracing_subscriber::registry().with(sink.clone()).init();
+//! ```
+use crate::LogDatabase;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+use tracing::field::{Field, Visit};
+use tracing::{span, Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// A span an event was recorded in
+pub struct SpanRecord {
+    pub name: String,
+    pub fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// An application log event, stored as the `content` of a log
+pub struct AppLogEntry {
+    /// Level of the event (ex: "WARN")
+    pub level: String,
+    /// Target of the event (usually the module path)
+    pub target: String,
+    pub message: String,
+    pub fields: BTreeMap<String, String>,
+    /// Spans the event was recorded in, outermost first
+    pub spans: Vec<SpanRecord>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogSinkOptions {
+    /// The logtype events are stored with
+    pub logtype: String,
+    /// Least severe level that is recorded
+    pub level: Level,
+    /// Maximum number of events waiting to be written
+    pub capacity: usize,
+    /// Maximum number of events written in a single transaction
+    pub batch_size: usize,
+    /// Events with a target starting with any of these are ignored
+    ///
+    /// This should always include `sqlx`, as writing logs creates `sqlx` events.
+    pub ignored_targets: Vec<String>,
+}
+
+impl Default for LogSinkOptions {
+    fn default() -> Self {
+        Self {
+            logtype: String::from("app"),
+            level: Level::WARN,
+            capacity: 1024,
+            batch_size: 100,
+            ignored_targets: vec![String::from("sqlx")],
+        }
+    }
+}
+
+/// Field visitor that formats every field as a string
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{value:?}");
+        } else {
+            self.fields
+                .insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+}
+
+/// Fields of a span, stored in the span's extensions
+struct SpanFields(BTreeMap<String, String>);
+
+// sink
+#[derive(Clone)]
+pub struct LogSink {
+    sender: mpsc::Sender<AppLogEntry>,
+    dropped: Arc<AtomicU64>,
+    options: Arc<LogSinkOptions>,
+}
+
+impl LogSink {
+    /// Create a new [`LogSink`] and the worker that writes its events
+    ///
+    /// The worker must be spawned on a tokio runtime, and finishes once every
+    /// clone of the sink has been dropped.
+    ///
+    /// # Arguments:
+    /// * `logs` - [`LogDatabase`] events are written to
+    /// * `options` - [`LogSinkOptions`]
+    pub fn new(
+        logs: LogDatabase,
+        options: LogSinkOptions,
+    ) -> (LogSink, impl Future<Output = ()> + Send + 'static) {
+        let (sender, mut receiver) = mpsc::channel::<AppLogEntry>(options.capacity.max(1));
+        let logtype = options.logtype.clone();
+        let batch_size = options.batch_size.max(1);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let failed = dropped.clone();
+
+        let worker = async move {
+            let mut batch: Vec<AppLogEntry> = Vec::new();
+
+            while receiver.recv_many(&mut batch, batch_size).await > 0 {
+                let size = batch.len() as u64;
+
+                if logs
+                    .create_logs(
+                        batch
+                            .drain(..)
+                            .map(|e| {
+                                (
+                                    logtype.clone(),
+                                    serde_json::to_string::<AppLogEntry>(&e).unwrap(),
+                                )
+                            })
+                            .collect(),
+                    )
+                    .await
+                    .is_err()
+                {
+                    failed.fetch_add(size, Ordering::Relaxed);
+                }
+            }
+        };
+
+        (
+            LogSink {
+                sender,
+                dropped,
+                options: Arc::new(options),
+            },
+            worker,
+        )
+    }
+
+    /// Create a new [`LogSink`] and spawn its worker on the current tokio runtime
+    ///
+    /// # Arguments:
+    /// * `logs` - [`LogDatabase`] events are written to
+    /// * `options` - [`LogSinkOptions`]
+    pub fn spawn(logs: LogDatabase, options: LogSinkOptions) -> LogSink {
+        let (sink, worker) = Self::new(logs, options);
+        tokio::spawn(worker);
+        sink
+    }
+
+    /// Number of events dropped because the queue was full or they couldn't be written
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Check if events with the given level and target are recorded
+    fn accepts(&self, level: &Level, target: &str) -> bool {
+        (*level <= self.options.level)
+            && !self
+                .options
+                .ignored_targets
+                .iter()
+                .any(|t| target.starts_with(t.as_str()))
+    }
+
+    /// Queue an event without waiting
+    fn push(&self, entry: AppLogEntry) {
+        if self.sender.try_send(entry).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<S> Layer<S> for LogSink
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut visitor = FieldVisitor::default();
+            values.record(&mut visitor);
+
+            let mut extensions = span.extensions_mut();
+            match extensions.get_mut::<SpanFields>() {
+                Some(fields) => fields.0.extend(visitor.fields),
+                None => extensions.insert(SpanFields(visitor.fields)),
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+
+        if !self.accepts(meta.level(), meta.target()) {
+            return;
+        }
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        // collect spans
+        let mut spans: Vec<SpanRecord> = Vec::new();
+
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                spans.push(SpanRecord {
+                    name: span.name().to_string(),
+                    fields: match span.extensions().get::<SpanFields>() {
+                        Some(f) => f.0.clone(),
+                        None => BTreeMap::new(),
+                    },
+                });
+            }
+        }
+
+        self.push(AppLogEntry {
+            level: meta.level().to_string(),
+            target: meta.target().to_string(),
+            message: visitor.message,
+            fields: visitor.fields,
+            spans,
+        });
+    }
+}
+
+impl log::Log for LogSink {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let level = match metadata.level() {
+            log::Level::Error => Level::ERROR,
+            log::Level::Warn => Level::WARN,
+            log::Level::Info => Level::INFO,
+            log::Level::Debug => Level::DEBUG,
+            log::Level::Trace => Level::TRACE,
+        };
+
+        self.accepts(&level, metadata.target())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !log::Log::enabled(self, record.metadata()) {
+            return;
+        }
+
+        self.push(AppLogEntry {
+            level: record.level().to_string(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+            fields: BTreeMap::new(),
+            spans: Vec::new(),
+        });
+    }
+
+    fn flush(&self) {}
+}
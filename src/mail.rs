@@ -0,0 +1,223 @@
+//! # Mail
+//! Sending emails through a pluggable [`Mailer`]
+//!
+//! [`SmtpMailer`] (`smtp` feature) sends emails to an SMTP relay, [`FileMailer`] writes
+//! them to a directory, and [`MemoryMailer`] keeps them in memory (for tests).
+//!
+//! ```ignore
+//! let mailer = FileMailer::new("mail");
+//! mailer.send(&Email { to: String::from("user@example.com"), ..Default::default() }).await?;
+//! ```
+use crate::{utility, DefaultReturn};
+
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "smtp")]
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// An email with a plain text and an HTML body
+pub struct Email {
+    /// Address of the recipient
+    pub to: String,
+    pub subject: String,
+    /// Plain text body
+    pub text: String,
+    /// HTML body
+    pub html: String,
+}
+
+// ...
+/// Mail errors
+#[derive(Debug)]
+pub enum MailError {
+    ValueError,
+    Other,
+}
+
+impl MailError {
+    pub fn to_string(&self) -> String {
+        use MailError::*;
+        match self {
+            ValueError => String::from("One of the field values given is invalid."),
+            _ => String::from("An unspecified error has occured"),
+        }
+    }
+}
+
+impl<T: Default> Into<DefaultReturn<T>> for MailError {
+    fn into(self) -> DefaultReturn<T> {
+        DefaultReturn {
+            success: false,
+            message: self.to_string(),
+            payload: T::default(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, MailError>;
+
+/// Something that can send [`Email`]s
+pub trait Mailer: Send + Sync {
+    /// Send an [`Email`]
+    ///
+    /// # Arguments:
+    /// * `email` - [`Email`]
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<()>>;
+}
+
+/// [`Mailer`] that sends emails to an SMTP relay
+#[cfg(feature = "smtp")]
+#[derive(Clone)]
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+#[cfg(feature = "smtp")]
+impl SmtpMailer {
+    /// Create a new [`SmtpMailer`] connecting to `host` over TLS
+    ///
+    /// # Arguments:
+    /// * `host` - hostname of the SMTP relay
+    /// * `username` - SMTP username
+    /// * `password` - SMTP password
+    /// * `from` - sender of the emails (e.g. "Dorsal <noreply@example.com>")
+    pub fn new(host: &str, username: String, password: String, from: &str) -> Result<Self> {
+        let from = match from.parse::<Mailbox>() {
+            Ok(f) => f,
+            Err(_) => return Err(MailError::ValueError),
+        };
+
+        let transport = match AsyncSmtpTransport::<Tokio1Executor>::relay(host) {
+            Ok(t) => t.credentials(Credentials::new(username, password)).build(),
+            Err(_) => return Err(MailError::Other),
+        };
+
+        Ok(Self { transport, from })
+    }
+}
+
+#[cfg(feature = "smtp")]
+impl Mailer for SmtpMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let to = match email.to.parse::<Mailbox>() {
+                Ok(t) => t,
+                Err(_) => return Err(MailError::ValueError),
+            };
+
+            let message = match Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(&email.subject)
+                .multipart(MultiPart::alternative_plain_html(
+                    email.text.clone(),
+                    email.html.clone(),
+                )) {
+                Ok(m) => m,
+                Err(_) => return Err(MailError::ValueError),
+            };
+
+            match self.transport.send(message).await {
+                Ok(_) => Ok(()),
+                Err(_) => Err(MailError::Other),
+            }
+        })
+    }
+}
+
+/// [`Mailer`] that writes every email to a file (`{timestamp}-{id}.eml`) in a directory
+#[derive(Debug, Clone)]
+pub struct FileMailer {
+    pub dir: PathBuf,
+}
+
+impl FileMailer {
+    /// Create a new [`FileMailer`]
+    ///
+    /// # Arguments:
+    /// * `dir` - directory emails are written to (created if it doesn't exist)
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if email.to.is_empty() | email.to.contains(['\r', '\n']) {
+                return Err(MailError::ValueError);
+            }
+
+            let id = utility::random_id();
+            let boundary = format!("dorsal-{}", &id[..16]);
+
+            let message = format!(
+                "To: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\nContent-Type: multipart/alternative; boundary=\"{boundary}\"\r\n\r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n--{boundary}\r\nContent-Type: text/html; charset=utf-8\r\n\r\n{}\r\n--{boundary}--\r\n",
+                email.to,
+                email.subject.replace(['\r', '\n'], " "),
+                email.text,
+                email.html
+            );
+
+            if tokio::fs::create_dir_all(&self.dir).await.is_err() {
+                return Err(MailError::Other);
+            }
+
+            let path = self.dir.join(format!(
+                "{}-{}.eml",
+                utility::unix_epoch_timestamp(),
+                &id[..8]
+            ));
+
+            match tokio::fs::write(path, message).await {
+                Ok(_) => Ok(()),
+                Err(_) => Err(MailError::Other),
+            }
+        })
+    }
+}
+
+/// [`Mailer`] that keeps every email in memory
+#[derive(Debug, Default, Clone)]
+pub struct MemoryMailer {
+    sent: Arc<Mutex<Vec<Email>>>,
+}
+
+impl MemoryMailer {
+    /// Create a new [`MemoryMailer`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get every email sent so far
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// Remove every email sent so far
+    pub fn clear(&self) {
+        self.sent.lock().unwrap().clear();
+    }
+}
+
+impl Mailer for MemoryMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if email.to.is_empty() {
+                return Err(MailError::ValueError);
+            }
+
+            self.sent.lock().unwrap().push(email.clone());
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,110 @@
+//! # SSE
+//! Real-time notification delivery over Server-Sent Events (actix-web)
+//!
+//! Each [`Notification`] is sent as a `notification` event with its `id` as the event id,
+//! so browsers resume from the last notification they received (using the `Last-Event-ID`
+//! header) when they reconnect.
+//!
+//! ```ignore
+//! #[get("/api/notifications/stream")]
+//! pub async fn stream_request(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
+//!     // ... get the current user
+//!     dorsal::sse::notification_events(
+//!         &data.db.notifications,
+//!         &req,
+//!         user.user.username,
+//!         dorsal::SseOptions::default(),
+//!     )
+//!     .await
+//! }
+//! ```
+use crate::{Notification, NotificationDatabase};
+
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
+use futures_util::future;
+use futures_util::stream::{self, StreamExt};
+use std::convert::Infallible;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct SseOptions {
+    /// How often a comment is sent to keep the connection open
+    pub heartbeat: Duration,
+    /// How long clients wait before reconnecting
+    pub retry: Duration,
+}
+
+impl Default for SseOptions {
+    fn default() -> Self {
+        Self {
+            heartbeat: Duration::from_secs(15),
+            retry: Duration::from_secs(3),
+        }
+    }
+}
+
+/// Format a [`Notification`] as an event
+///
+/// # Arguments:
+/// * `notification` - [`Notification`]
+pub fn notification_event(notification: &Notification) -> String {
+    format!(
+        "id: {}\nevent: notification\ndata: {}\n\n",
+        notification.id,
+        serde_json::to_string::<Notification>(notification).unwrap()
+    )
+}
+
+/// Stream the new notifications of the given `user` as Server-Sent Events
+///
+/// # Arguments:
+/// * `notifications` - [`NotificationDatabase`]
+/// * `req` - the request, used to read the `Last-Event-ID` header
+/// * `user` - username of the notifications' recipient
+/// * `options` - [`SseOptions`]
+pub async fn notification_events(
+    notifications: &NotificationDatabase,
+    req: &HttpRequest,
+    user: String,
+    options: SseOptions,
+) -> HttpResponse {
+    let last_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string());
+
+    let events = match notifications.subscribe(user, last_id).await {
+        Ok(s) => s.map(|n| notification_event(&n)),
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    // heartbeats
+    let mut interval = tokio::time::interval(options.heartbeat);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval.reset(); // don't send a heartbeat immediately
+
+    let heartbeats = stream::unfold(interval, |mut interval| async move {
+        interval.tick().await;
+        Some((String::from(": heartbeat\n\n"), interval))
+    });
+
+    // ...
+    // the response ends when the notification stream ends (ex: pubsub closed),
+    // which is marked by `None`
+    let chunks = stream::select(
+        events.map(Some).chain(stream::once(async { None })),
+        heartbeats.map(Some),
+    )
+    .take_while(|chunk| future::ready(chunk.is_some()))
+    .filter_map(future::ready);
+
+    let body = stream::once(async move { format!("retry: {}\n\n", options.retry.as_millis()) })
+        .chain(chunks)
+        .map(|chunk| Ok::<Bytes, Infallible>(Bytes::from(chunk)));
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body)
+}
@@ -0,0 +1,384 @@
+//! # Tokens
+//! Signed stateless session tokens (JWT, HS256)
+//!
+//! Tokens embed the user's username, role and elevation so they can be verified
+//! without any database access. Keys are identified by their `kid`, which allows
+//! old keys to keep verifying tokens while new tokens are signed with the current key.
+use crate::{utility, DefaultReturn};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// Token header
+struct TokenHeader {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+/// The claims embedded in a token
+pub struct TokenClaims {
+    /// Username of the user the token belongs to
+    pub sub: String,
+    /// Role of the user when the token was issued
+    pub role: String,
+    /// Elevation of the user's role when the token was issued
+    pub elevation: i32,
+    /// Time the token was issued at (seconds since epoch)
+    pub iat: u64,
+    /// Time the token expires at (seconds since epoch)
+    pub exp: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+/// A single signing key
+pub struct TokenKey {
+    /// Key identifier, included in the header of every token signed with this key
+    pub kid: String,
+    pub secret: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The set of keys used to sign and verify tokens
+pub struct TokenKeyring {
+    /// `kid` of the key used to sign new tokens
+    pub current: String,
+    /// All keys that are accepted when verifying tokens
+    pub keys: Vec<TokenKey>,
+    /// How long tokens are valid for (in seconds)
+    pub lifetime: u64,
+    /// How long after expiring a token can still be refreshed (in seconds)
+    ///
+    /// Refreshing checks the user in the database, so bans, role changes and
+    /// revocations apply. This should be about as long as a user may stay away
+    /// without having to sign in again.
+    pub refresh_window: u64,
+}
+
+impl Default for TokenKeyring {
+    fn default() -> Self {
+        Self {
+            current: String::new(),
+            keys: Vec::new(),
+            lifetime: 60 * 15,
+            refresh_window: 60 * 60 * 24,
+        }
+    }
+}
+
+// ...
+/// Token errors
+#[derive(Debug)]
+pub enum TokenError {
+    Malformed,
+    UnknownKey,
+    InvalidSignature,
+    Expired,
+}
+
+impl TokenError {
+    pub fn to_string(&self) -> String {
+        use TokenError::*;
+        match self {
+            Malformed => String::from("Token is malformed."),
+            UnknownKey => String::from("Token was signed with an unknown key."),
+            InvalidSignature => String::from("Token signature is invalid."),
+            Expired => String::from("Token has expired."),
+        }
+    }
+}
+
+impl<T: Default> Into<DefaultReturn<T>> for TokenError {
+    fn into(self) -> DefaultReturn<T> {
+        DefaultReturn {
+            success: false,
+            message: self.to_string(),
+            payload: T::default(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, TokenError>;
+
+/// Get the current time in seconds since epoch
+pub fn now() -> u64 {
+    (utility::unix_epoch_timestamp() / 1000) as u64
+}
+
+impl TokenKeyring {
+    /// Get a [`TokenKey`] by its `kid`
+    fn get_key(&self, kid: &str) -> Result<&TokenKey> {
+        match self.keys.iter().find(|k| k.kid == kid) {
+            Some(k) => Ok(k),
+            None => Err(TokenError::UnknownKey),
+        }
+    }
+
+    /// Compute the signature of `input` using the given key
+    fn mac(key: &TokenKey, input: &str) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key.secret)
+            .expect("HMAC accepts keys of any size");
+        mac.update(input.as_bytes());
+        mac
+    }
+
+    /// Sign the given claims with the current key
+    ///
+    /// # Arguments:
+    /// * `claims` - [`TokenClaims`]
+    pub fn sign(&self, claims: &TokenClaims) -> Result<String> {
+        let key = self.get_key(&self.current)?;
+
+        let header = TokenHeader {
+            alg: String::from("HS256"),
+            typ: String::from("JWT"),
+            kid: key.kid.clone(),
+        };
+
+        let input = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_string(&header).unwrap()),
+            URL_SAFE_NO_PAD.encode(serde_json::to_string(claims).unwrap())
+        );
+
+        let signature = URL_SAFE_NO_PAD.encode(Self::mac(key, &input).finalize().into_bytes());
+        Ok(format!("{input}.{signature}"))
+    }
+
+    /// Create and sign claims for the given user, valid for [`TokenKeyring::lifetime`]
+    ///
+    /// # Arguments:
+    /// * `username` - `String` of the user's username
+    /// * `role` - `String` of the user's role
+    /// * `elevation` - the elevation of the user's role
+    pub fn issue(&self, username: String, role: String, elevation: i32) -> Result<String> {
+        let iat = now();
+        self.sign(&TokenClaims {
+            sub: username,
+            role,
+            elevation,
+            iat,
+            exp: iat + self.lifetime,
+        })
+    }
+
+    /// Verify the signature of a token and return its claims, **without** checking expiry
+    ///
+    /// # Arguments:
+    /// * `token` - `&str` of the token
+    pub fn verify_signature(&self, token: &str) -> Result<TokenClaims> {
+        let mut parts = token.split('.');
+        let (header, claims, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(c), Some(s)) => (h, c, s),
+            _ => return Err(TokenError::Malformed),
+        };
+
+        if parts.next().is_some() {
+            return Err(TokenError::Malformed);
+        }
+
+        // decode header
+        let header = match URL_SAFE_NO_PAD
+            .decode(header)
+            .ok()
+            .and_then(|h| serde_json::from_slice::<TokenHeader>(&h).ok())
+        {
+            Some(h) => h,
+            None => return Err(TokenError::Malformed),
+        };
+
+        if header.alg != "HS256" {
+            return Err(TokenError::Malformed);
+        }
+
+        // check signature
+        let key = self.get_key(&header.kid)?;
+        let signature = match URL_SAFE_NO_PAD.decode(signature) {
+            Ok(s) => s,
+            Err(_) => return Err(TokenError::Malformed),
+        };
+
+        let input = &token[..token.rfind('.').unwrap()];
+        if Self::mac(key, input).verify_slice(&signature).is_err() {
+            return Err(TokenError::InvalidSignature);
+        }
+
+        // decode claims
+        match URL_SAFE_NO_PAD
+            .decode(claims)
+            .ok()
+            .and_then(|c| serde_json::from_slice::<TokenClaims>(&c).ok())
+        {
+            Some(c) => Ok(c),
+            None => Err(TokenError::Malformed),
+        }
+    }
+
+    /// Verify a token and return its claims
+    ///
+    /// # Arguments:
+    /// * `token` - `&str` of the token
+    pub fn verify(&self, token: &str) -> Result<TokenClaims> {
+        let claims = self.verify_signature(token)?;
+
+        if claims.exp <= now() {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(claims)
+    }
+
+    /// Verify a token that may have expired less than [`TokenKeyring::refresh_window`] ago,
+    /// and return its claims
+    ///
+    /// # Arguments:
+    /// * `token` - `&str` of the token
+    pub fn verify_refreshable(&self, token: &str) -> Result<TokenClaims> {
+        let claims = self.verify_signature(token)?;
+
+        if claims.exp.saturating_add(self.refresh_window) <= now() {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyring(keys: &[(&str, &str)], current: &str) -> TokenKeyring {
+        TokenKeyring {
+            current: current.to_string(),
+            keys: keys
+                .iter()
+                .map(|(kid, secret)| TokenKey {
+                    kid: kid.to_string(),
+                    secret: secret.as_bytes().to_vec(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn claims(exp: u64) -> TokenClaims {
+        TokenClaims {
+            sub: String::from("user"),
+            role: String::from("member"),
+            elevation: 0,
+            iat: exp.saturating_sub(60),
+            exp,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let keys = keyring(&[("a", "secret")], "a");
+        let token = keys
+            .issue(String::from("user"), String::from("admin"), 10)
+            .unwrap();
+
+        let claims = keys.verify(&token).unwrap();
+        assert_eq!(claims.sub, "user");
+        assert_eq!(claims.role, "admin");
+        assert_eq!(claims.elevation, 10);
+        assert_eq!(claims.exp, claims.iat + keys.lifetime);
+    }
+
+    #[test]
+    fn tampered() {
+        let keys = keyring(&[("a", "secret")], "a");
+        let token = keys.sign(&claims(now() + 60)).unwrap();
+
+        // claims changed without signing them again
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let forged = URL_SAFE_NO_PAD.encode(
+            serde_json::to_string(&TokenClaims {
+                role: String::from("admin"),
+                ..claims(now() + 60)
+            })
+            .unwrap(),
+        );
+        parts[1] = &forged;
+
+        assert!(matches!(
+            keys.verify(&parts.join(".")),
+            Err(TokenError::InvalidSignature)
+        ));
+
+        // signed with another secret
+        let other = keyring(&[("a", "other")], "a");
+        assert!(matches!(
+            other.verify(&token),
+            Err(TokenError::InvalidSignature)
+        ));
+
+        assert!(matches!(keys.verify("a.b"), Err(TokenError::Malformed)));
+        assert!(matches!(keys.verify("a.b.c.d"), Err(TokenError::Malformed)));
+    }
+
+    #[test]
+    fn expired() {
+        let keys = keyring(&[("a", "secret")], "a");
+
+        let token = keys.sign(&claims(now() - 10)).unwrap();
+        assert!(matches!(keys.verify(&token), Err(TokenError::Expired)));
+        assert_eq!(keys.verify_signature(&token).unwrap().sub, "user");
+        assert!(keys.verify_refreshable(&token).is_ok());
+
+        // expired longer than the refresh window ago
+        let token = keys
+            .sign(&claims(now() - keys.refresh_window - 10))
+            .unwrap();
+        assert!(matches!(
+            keys.verify_refreshable(&token),
+            Err(TokenError::Expired)
+        ));
+    }
+
+    #[test]
+    fn wrong_kid() {
+        let keys = keyring(&[("a", "secret")], "a");
+        let token = keyring(&[("b", "secret")], "b")
+            .sign(&claims(now() + 60))
+            .unwrap();
+
+        assert!(matches!(keys.verify(&token), Err(TokenError::UnknownKey)));
+
+        // signing with a missing current key fails
+        assert!(matches!(
+            keyring(&[("a", "secret")], "b").sign(&claims(now() + 60)),
+            Err(TokenError::UnknownKey)
+        ));
+    }
+
+    #[test]
+    fn rotated_key() {
+        let old = keyring(&[("a", "first")], "a");
+        let old_token = old.sign(&claims(now() + 60)).unwrap();
+
+        // new tokens are signed with "b", tokens signed with "a" are still accepted
+        let rotated = keyring(&[("a", "first"), ("b", "second")], "b");
+        let new_token = rotated.sign(&claims(now() + 60)).unwrap();
+
+        assert!(rotated.verify(&old_token).is_ok());
+        assert!(rotated.verify(&new_token).is_ok());
+        assert!(matches!(
+            old.verify(&new_token),
+            Err(TokenError::UnknownKey)
+        ));
+
+        // once "a" is removed, its tokens are rejected
+        let retired = keyring(&[("b", "second")], "b");
+        assert!(matches!(
+            retired.verify(&old_token),
+            Err(TokenError::UnknownKey)
+        ));
+        assert!(retired.verify(&new_token).is_ok());
+    }
+}
@@ -33,3 +33,12 @@ pub fn unix_epoch_timestamp() -> u128 {
 
     return time_since.as_millis();
 }
+
+// queries
+/// Escape the wildcards of a `LIKE` pattern (use with `ESCAPE '!'`)
+pub fn escape_like(input: &str) -> String {
+    input
+        .replace('!', "!!")
+        .replace('%', "!%")
+        .replace('_', "!_")
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::auth_db::{AuditAction, AuditContext, DatabaseOptions};
+use dorsal::{AuthDatabase, LogDatabase};
+
+/// Create an [`AuthDatabase`] (and its logs table) with unique tables
+async fn setup() -> AuthDatabase {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    logs.init().await;
+
+    let auth = AuthDatabase::new(
+        db,
+        DatabaseOptions {
+            table: unique("Users"),
+            logs_table: logs.options.table.clone(),
+            notifications_table: None,
+            prefix: unique("user"),
+            ..Default::default()
+        },
+    )
+    .await
+    .with_logs(logs);
+    auth.init().await;
+
+    auth
+}
+
+fn ctx(actor: &str) -> AuditContext {
+    AuditContext {
+        actor: String::from(actor),
+        ip: Some(String::from("127.0.0.1")),
+    }
+}
+
+#[tokio::test]
+async fn account_changes_are_audited() {
+    let auth = setup().await;
+
+    for username in ["admin", "target", "other"] {
+        auth.create_user(String::from(username)).await.unwrap();
+    }
+
+    auth.update_user_role(ctx("admin"), String::from("target"), String::from("helper"))
+        .await
+        .unwrap();
+    auth.update_user_secondary_token(ctx("target"), String::from("target"))
+        .await
+        .unwrap();
+    auth.ban_user(ctx("admin"), String::from("target"))
+        .await
+        .unwrap();
+    auth.ban_user(ctx("admin"), String::from("other"))
+        .await
+        .unwrap();
+
+    let entries = auth
+        .get_user_audit_log(String::from("target"), None)
+        .await
+        .unwrap();
+
+    // only entries targeting the user
+    assert_eq!(entries.len(), 3);
+    let entry = |action: AuditAction| entries.iter().find(|e| e.action == action).unwrap();
+
+    let ban = entry(AuditAction::Ban);
+    assert_eq!(ban.before.as_deref(), Some("helper"));
+    assert_eq!(ban.after.as_deref(), Some("banned"));
+
+    let role_change = entry(AuditAction::RoleChange);
+    assert_eq!(role_change.actor, "admin");
+    assert_eq!(role_change.ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(role_change.before.as_deref(), Some("member"));
+
+    // the new secondary token is never recorded
+    let rotation = entry(AuditAction::TokenRotation);
+    assert!(rotation.before.is_none() & rotation.after.is_none());
+
+    // wildcards in usernames don't match other users' entries
+    assert!(auth
+        .get_user_audit_log(String::from("%"), None)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn concurrent_role_changes_record_the_replaced_role() {
+    let auth = setup().await;
+    auth.create_user(String::from("contested")).await.unwrap();
+
+    let changes = (0..8).map(|i| {
+        auth.update_user_role(ctx("admin"), String::from("contested"), format!("role{i}"))
+    });
+
+    for changed in futures_util::future::join_all(changes).await {
+        changed.unwrap();
+    }
+
+    let entries = auth
+        .get_user_audit_log(String::from("contested"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(entries.len(), 8);
+
+    // every entry replaced a different role, so the entries form a single chain
+    let mut before: Vec<String> = entries.iter().filter_map(|e| e.before.clone()).collect();
+    before.sort();
+    before.dedup();
+    assert_eq!(before.len(), 8);
+    assert!(before.contains(&String::from("member")));
+}
@@ -0,0 +1,171 @@
+//! Shared test setup
+//!
+//! Tests use a SQLite database in a temporary directory, and need a Redis server
+//! on `127.0.0.1:6379` (like [`dorsal::CacheDB`]).
+#![allow(dead_code)]
+use dorsal::{DatabaseOpts, PubSub, StarterDatabase};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Once};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+static SETUP: Once = Once::new();
+
+/// Create a [`StarterDatabase`] (every test in the same binary shares its SQLite file)
+pub async fn database() -> StarterDatabase {
+    SETUP.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("dorsal-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::File::create(dir.join("main.db")).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+    });
+
+    let mut db = StarterDatabase::new(DatabaseOpts {
+        _type: None,
+        host: None,
+        user: String::new(),
+        pass: String::new(),
+        name: String::new(),
+    })
+    .await;
+
+    db.pubsub = PubSub::local();
+    db
+}
+
+/// Get a unique name (for tables, cache prefixes and usernames)
+pub fn unique(name: &str) -> String {
+    format!("{name}_{}", &dorsal::utility::random_id()[..12])
+}
+
+#[derive(Debug, Clone)]
+/// A request received by a [`Stub`]
+pub struct StubRequest {
+    pub method: String,
+    pub path: String,
+    /// Headers (with lowercase names)
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// A local HTTP server answering every request with the given handler
+pub struct Stub {
+    /// Base URL of the server (ex: "http://127.0.0.1:1234")
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+impl Stub {
+    /// Start a new [`Stub`]
+    ///
+    /// # Arguments:
+    /// * `handler` - returns the status and body of the response to a request
+    pub async fn start<F>(handler: F) -> Stub
+    where
+        F: Fn(&StubRequest) -> (u16, String) + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Arc<Mutex<Vec<StubRequest>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let handler = Arc::new(handler);
+        let received = requests.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(s) => s,
+                    Err(_) => return,
+                };
+
+                let handler = handler.clone();
+                let received = received.clone();
+
+                tokio::spawn(async move {
+                    let request = match read_request(&mut socket).await {
+                        Some(r) => r,
+                        None => return,
+                    };
+
+                    let (status, body) = handler(&request);
+                    received.lock().unwrap().push(request);
+
+                    let response = format!(
+                        "HTTP/1.1 {status} Stub\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+
+        Stub { url, requests }
+    }
+
+    /// Get every request received so far
+    pub fn requests(&self) -> Vec<StubRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Read a single HTTP/1.1 request
+async fn read_request(socket: &mut tokio::net::TcpStream) -> Option<StubRequest> {
+    let mut data: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 4096];
+
+    // headers
+    let end = loop {
+        let read = socket.read(&mut buffer).await.ok()?;
+
+        if read == 0 {
+            return None;
+        }
+
+        data.extend_from_slice(&buffer[..read]);
+
+        if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&data[..end]).to_string();
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+
+    // body
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+
+    let mut body = data[end + 4..].to_vec();
+
+    while body.len() < length {
+        let read = socket.read(&mut buffer).await.ok()?;
+
+        if read == 0 {
+            break;
+        }
+
+        body.extend_from_slice(&buffer[..read]);
+    }
+
+    Some(StubRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    })
+}
@@ -0,0 +1,224 @@
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::auth_db::{AuditContext, DatabaseOptions, LevelLog, RoleLevel};
+use dorsal::db::special::invite_db::InviteError;
+use dorsal::{AuthDatabase, InviteDatabase, LogDatabase};
+
+#[tokio::test]
+async fn invites() {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    logs.init().await;
+
+    logs.create_typed::<LevelLog>(&RoleLevel {
+        elevation: 10,
+        name: String::from("admin"),
+        permissions: Vec::new(),
+    })
+    .await
+    .unwrap();
+
+    let auth = AuthDatabase::new(
+        db.clone(),
+        DatabaseOptions {
+            table: unique("Users"),
+            logs_table: logs.options.table.clone(),
+            notifications_table: None,
+            prefix: unique("user"),
+            logs_prefix: unique("level"),
+            ..Default::default()
+        },
+    )
+    .await
+    .with_logs(logs.clone());
+    assert!(auth.init().await);
+
+    let invites = InviteDatabase::new(
+        db.clone(),
+        auth.clone(),
+        dorsal::db::special::invite_db::DatabaseOptions {
+            table: unique("Invites"),
+        },
+    )
+    .await;
+    assert!(invites.init().await);
+
+    for username in ["a_b", "aXb", "spammer"] {
+        auth.create_user(String::from(username)).await.unwrap();
+    }
+
+    // members can't invite admins
+    assert!(matches!(
+        invites
+            .create_invite(String::from("a_b"), String::from("admin"), 0, None)
+            .await,
+        Err(InviteError::NotAllowed)
+    ));
+
+    // roles without a level can't be given
+    for role in ["moderator", "%"] {
+        assert!(matches!(
+            invites
+                .create_invite(String::from("a_b"), String::from(role), 0, None)
+                .await,
+            Err(InviteError::ValueError)
+        ));
+    }
+
+    let invite = invites
+        .create_invite(String::from("a_b"), String::from("member"), 0, None)
+        .await
+        .unwrap();
+
+    // banned users keep their username
+    auth.ban_user(
+        AuditContext {
+            actor: String::from("a_b"),
+            ip: None,
+        },
+        String::from("spammer"),
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        invites
+            .create_user_with_invite(invite.code.clone(), String::from("spammer"))
+            .await,
+        Err(InviteError::ValueError)
+    ));
+
+    // concurrent signups with the same username create a single user
+    let (first, second) = tokio::join!(
+        invites.create_user_with_invite(invite.code.clone(), String::from("new_user")),
+        invites.create_user_with_invite(invite.code.clone(), String::from("new_user")),
+    );
+    assert!(first.is_ok() ^ second.is_ok());
+    assert_eq!(
+        invites.get_invite(invite.code.clone()).await.unwrap().uses,
+        1
+    );
+
+    // "_" doesn't match other users
+    assert_eq!(
+        invites
+            .get_invited_users(String::from("a_b"))
+            .await
+            .unwrap(),
+        vec![String::from("new_user")]
+    );
+    assert!(invites
+        .get_invited_users(String::from("aXb"))
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn revoking_invites() {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    logs.init().await;
+
+    logs.create_typed::<LevelLog>(&RoleLevel {
+        elevation: 10,
+        name: String::from("admin"),
+        permissions: Vec::new(),
+    })
+    .await
+    .unwrap();
+
+    let auth = AuthDatabase::new(
+        db.clone(),
+        DatabaseOptions {
+            table: unique("Users"),
+            logs_table: logs.options.table.clone(),
+            notifications_table: None,
+            prefix: unique("user"),
+            logs_prefix: unique("level"),
+            ..Default::default()
+        },
+    )
+    .await
+    .with_logs(logs.clone());
+    assert!(auth.init().await);
+
+    let invites = InviteDatabase::new(
+        db.clone(),
+        auth.clone(),
+        dorsal::db::special::invite_db::DatabaseOptions {
+            table: unique("Invites"),
+        },
+    )
+    .await;
+    assert!(invites.init().await);
+
+    for username in ["creator", "member", "admin"] {
+        auth.create_user(String::from(username)).await.unwrap();
+    }
+
+    let ctx = AuditContext {
+        actor: String::from("admin"),
+        ip: None,
+    };
+    auth.update_user_role(ctx, String::from("admin"), String::from("admin"))
+        .await
+        .unwrap();
+
+    let first = invites
+        .create_invite(String::from("creator"), String::from("member"), 0, None)
+        .await
+        .unwrap();
+    let second = invites
+        .create_invite(String::from("creator"), String::from("member"), 0, None)
+        .await
+        .unwrap();
+
+    // other members can't revoke the invite
+    assert!(matches!(
+        invites
+            .revoke_invite(String::from("member"), first.code.clone())
+            .await,
+        Err(InviteError::NotAllowed)
+    ));
+    assert!(
+        !invites
+            .get_invite(first.code.clone())
+            .await
+            .unwrap()
+            .revoked
+    );
+
+    // the creator can
+    invites
+        .revoke_invite(String::from("creator"), first.code.clone())
+        .await
+        .unwrap();
+    assert!(invites.get_invite(first.code).await.unwrap().revoked);
+
+    // so can more elevated users
+    invites
+        .revoke_invite(String::from("admin"), second.code.clone())
+        .await
+        .unwrap();
+    assert!(invites.get_invite(second.code).await.unwrap().revoked);
+}
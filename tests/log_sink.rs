@@ -0,0 +1,145 @@
+#![cfg(feature = "tracing")]
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::log_db::{DatabaseOptions, LogQuery};
+use dorsal::log_sink::AppLogEntry;
+use dorsal::{LogDatabase, LogSink, LogSinkOptions};
+
+use std::time::Duration;
+use tracing_subscriber::prelude::*;
+
+/// Create a [`LogDatabase`] with a unique table (created if `init` is true)
+async fn setup(init: bool) -> LogDatabase {
+    let logs = LogDatabase::new(
+        database().await,
+        DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    if init {
+        assert!(logs.init().await);
+    }
+
+    logs
+}
+
+/// Log a message through the [`log::Log`] backend of a sink
+fn warn(sink: &LogSink, target: &str, message: &str) {
+    log::Log::log(
+        sink,
+        &log::Record::builder()
+            .level(log::Level::Warn)
+            .target(target)
+            .args(format_args!("{message}"))
+            .build(),
+    );
+}
+
+#[tokio::test]
+async fn full_queue_drops_events() {
+    let logs = setup(true).await;
+    let (sink, worker) = LogSink::new(
+        logs.clone(),
+        LogSinkOptions {
+            capacity: 2,
+            ..Default::default()
+        },
+    );
+
+    // the worker isn't running, so only `capacity` events fit in the queue
+    for i in 0..5 {
+        warn(&sink, "app", &format!("event {i}"));
+    }
+
+    assert_eq!(sink.dropped(), 3);
+
+    // events below the level or from ignored targets aren't queued or dropped
+    log::Log::log(
+        &sink,
+        &log::Record::builder()
+            .level(log::Level::Info)
+            .target("app")
+            .args(format_args!("info"))
+            .build(),
+    );
+    warn(&sink, "sqlx::query", "ignored");
+    assert_eq!(sink.dropped(), 3);
+
+    // the worker writes the queued events and finishes once the sink is dropped
+    drop(sink);
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .unwrap();
+
+    let page = logs.query(LogQuery::new().logtype("app")).await.unwrap();
+    let mut messages: Vec<String> = page
+        .logs
+        .iter()
+        .map(|l| {
+            serde_json::from_str::<AppLogEntry>(&l.content)
+                .unwrap()
+                .message
+        })
+        .collect();
+    messages.sort();
+
+    assert_eq!(messages, vec!["event 0", "event 1"]);
+}
+
+#[tokio::test]
+async fn failed_batches_are_counted_as_dropped() {
+    // the logs table doesn't exist, so every batch fails
+    let sink = LogSink::spawn(setup(false).await, LogSinkOptions::default());
+
+    for i in 0..3 {
+        warn(&sink, "app", &format!("event {i}"));
+    }
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while sink.dropped() < 3 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(sink.dropped(), 3);
+}
+
+#[tokio::test]
+async fn events_record_fields_and_spans() {
+    let logs = setup(true).await;
+    let (sink, worker) = LogSink::new(logs.clone(), LogSinkOptions::default());
+
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(sink), || {
+        let span = tracing::warn_span!("request", path = "/login");
+        let _entered = span.enter();
+
+        tracing::warn!(user = "alice", attempts = 3, "login failed");
+    });
+
+    // the subscriber (and the sink in it) was dropped
+    tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .unwrap();
+
+    let page = logs.query(LogQuery::new().logtype("app")).await.unwrap();
+    assert_eq!(page.logs.len(), 1);
+
+    let entry = serde_json::from_str::<AppLogEntry>(&page.logs[0].content).unwrap();
+    assert_eq!(entry.level, "WARN");
+    assert_eq!(entry.message, "login failed");
+    assert_eq!(entry.fields.get("user").map(String::as_str), Some("alice"));
+    assert_eq!(entry.fields.get("attempts").map(String::as_str), Some("3"));
+    assert_eq!(entry.spans.len(), 1);
+    assert_eq!(entry.spans[0].name, "request");
+    assert_eq!(
+        entry.spans[0].fields.get("path").map(String::as_str),
+        Some("/login")
+    );
+}
@@ -0,0 +1,493 @@
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::log_db::{
+    DatabaseOptions, ImportMode, Log, LogFilter, LogKind, LogOrder, LogQuery, RetentionPolicy,
+};
+use dorsal::LogDatabase;
+
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct PasteView {
+    paste: String,
+    views: u32,
+}
+
+struct PasteViewLog;
+
+impl LogKind for PasteViewLog {
+    const LOGTYPE: &'static str = "paste_view";
+    type Payload = PasteView;
+}
+
+/// Create a [`LogDatabase`] with a unique table
+async fn setup(options: DatabaseOptions) -> LogDatabase {
+    let logs = LogDatabase::new(
+        database().await,
+        DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..options
+        },
+    )
+    .await;
+
+    assert!(logs.init().await);
+    logs
+}
+
+#[tokio::test]
+async fn query_filters_and_paginates() {
+    let logs = setup(Default::default()).await;
+
+    // logs created together share their timestamp, pages are ordered by id too
+    let created = logs
+        .create_logs(
+            (0..25)
+                .map(|i| (String::from("page"), format!("entry {i}")))
+                .collect(),
+        )
+        .await
+        .unwrap();
+
+    logs.create_log(String::from("other"), String::from("100% other"))
+        .await
+        .unwrap();
+
+    let mut seen: Vec<String> = Vec::new();
+    let mut cursor: Option<String> = None;
+    let mut pages = 0;
+
+    loop {
+        let page = logs
+            .query(LogQuery::new().logtype("page").limit(10).cursor(cursor))
+            .await
+            .unwrap();
+
+        seen.extend(page.logs.into_iter().map(|l| l.id));
+        pages += 1;
+
+        if page.next.is_none() {
+            break;
+        }
+
+        cursor = page.next;
+    }
+
+    assert_eq!(pages, 3);
+
+    let mut expected: Vec<String> = created.iter().map(|l| l.id.clone()).collect();
+    expected.sort();
+    expected.reverse();
+    assert_eq!(seen, expected);
+
+    // ascending order
+    let page = logs
+        .query(
+            LogQuery::new()
+                .logtype("page")
+                .order(LogOrder::Ascending)
+                .limit(1),
+        )
+        .await
+        .unwrap();
+    assert_eq!(page.logs[0].id, expected[24]);
+
+    // wildcards in the content filter are matched literally
+    let page = logs.query(LogQuery::new().content("0%")).await.unwrap();
+    assert_eq!(page.logs.len(), 1);
+    assert_eq!(page.logs[0].logtype, "other");
+
+    let page = logs
+        .query(LogQuery::new().logtype("page").content("entry 1"))
+        .await
+        .unwrap();
+    assert_eq!(page.logs.len(), 11);
+
+    // time range
+    let timestamp = created[0].timestamp;
+    let page = logs
+        .query(LogQuery::new().from(timestamp).to(timestamp).limit(50))
+        .await
+        .unwrap();
+    assert!(page.logs.iter().all(|l| l.timestamp == timestamp));
+    assert!(page.logs.len() >= 25);
+
+    assert!(logs
+        .query(LogQuery::new().to(timestamp - 1))
+        .await
+        .unwrap()
+        .logs
+        .is_empty());
+
+    // malformed cursors are rejected
+    assert!(logs
+        .query(LogQuery::new().cursor(Some(String::from("nope"))))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn created_logs_are_returned() {
+    let logs = setup(Default::default()).await;
+
+    let log = logs
+        .create_log(String::from("created"), String::from("content"))
+        .await
+        .unwrap();
+
+    assert_eq!(log.logtype, "created");
+    assert_eq!(log.content, "content");
+    assert!(!log.id.is_empty() & (log.timestamp > 0));
+    assert!(logs.get_log_by_id(log.id.clone()).await.unwrap() == log);
+
+    let batch = logs
+        .create_logs(vec![
+            (String::from("first"), String::from("1")),
+            (String::from("second"), String::from("2")),
+        ])
+        .await
+        .unwrap();
+
+    assert_eq!(batch.len(), 2);
+    assert_ne!(batch[0].id, batch[1].id);
+
+    for created in batch {
+        assert!(logs.get_log_by_id(created.id.clone()).await.unwrap() == created);
+    }
+
+    assert!(logs.create_logs(Vec::new()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn typed_logs_round_trip() {
+    let logs = setup(Default::default()).await;
+
+    let mut created = Vec::new();
+    for views in 0..3 {
+        created.push(
+            logs.create_typed::<PasteViewLog>(&PasteView {
+                paste: String::from("hello"),
+                views,
+            })
+            .await
+            .unwrap(),
+        );
+    }
+
+    let stored = logs
+        .get_typed::<PasteViewLog>(created[1].id.clone())
+        .await
+        .unwrap();
+    assert!(stored == created[1]);
+    assert_eq!(stored.payload.views, 1);
+
+    // logs of other types and malformed contents aren't decoded
+    let other = logs
+        .create_log(String::from("other"), String::from("{}"))
+        .await
+        .unwrap();
+    assert!(logs.get_typed::<PasteViewLog>(other.id).await.is_err());
+
+    let malformed = logs
+        .create_log(String::from("paste_view"), String::from("not json"))
+        .await
+        .unwrap();
+    assert!(logs
+        .get_typed::<PasteViewLog>(malformed.id.clone())
+        .await
+        .is_err());
+    logs.delete_log(malformed.id).await.unwrap();
+
+    // listing ignores the query's logtype
+    let page = logs
+        .list_typed::<PasteViewLog>(LogQuery::new().logtype("other").limit(2))
+        .await
+        .unwrap();
+    assert_eq!(page.logs.len(), 2);
+    assert!(page.next.is_some());
+
+    let rest = logs
+        .list_typed::<PasteViewLog>(LogQuery::new().limit(2).cursor(page.next))
+        .await
+        .unwrap();
+    assert_eq!(rest.logs.len(), 1);
+    assert!(rest.next.is_none());
+}
+
+/// Write the given logs as newline-delimited JSON
+fn ndjson(logs: &[Log]) -> String {
+    logs.iter()
+        .map(|l| serde_json::to_string(l).unwrap() + "\n")
+        .collect()
+}
+
+#[tokio::test]
+async fn prune_applies_retention_policies() {
+    let logs = setup(DatabaseOptions {
+        retention: vec![
+            RetentionPolicy {
+                logtype: String::from("aged"),
+                max_age: Some(60_000),
+                max_count: None,
+            },
+            RetentionPolicy {
+                logtype: String::from("counted"),
+                max_age: None,
+                max_count: Some(3),
+            },
+        ],
+        prune_batch_size: 2,
+        ..Default::default()
+    })
+    .await;
+
+    // logs an hour old
+    let old = dorsal::utility::unix_epoch_timestamp() - 3_600_000;
+    let imported: Vec<Log> = (0..5)
+        .map(|i| Log {
+            id: format!("old{i}"),
+            logtype: String::from("aged"),
+            timestamp: old,
+            content: String::new(),
+        })
+        .collect();
+
+    logs.import(ndjson(&imported).as_bytes(), ImportMode::Skip)
+        .await
+        .unwrap();
+
+    let fresh = logs
+        .create_log(String::from("aged"), String::new())
+        .await
+        .unwrap();
+
+    let mut counted = Vec::new();
+    for i in 0..7 {
+        counted.push(
+            logs.create_log(String::from("counted"), format!("{i}"))
+                .await
+                .unwrap(),
+        );
+    }
+
+    // logs without a policy are kept
+    let kept = logs
+        .create_log(String::from("kept"), String::new())
+        .await
+        .unwrap();
+
+    let report = logs.prune().await.unwrap();
+    assert_eq!(report.removed, 9);
+    assert_eq!(report.by_logtype.get("aged"), Some(&5));
+    assert_eq!(report.by_logtype.get("counted"), Some(&4));
+
+    assert!(logs.get_log_by_id(imported[0].id.clone()).await.is_err());
+    assert!(logs.get_log_by_id(fresh.id).await.is_ok());
+    assert!(logs.get_log_by_id(kept.id).await.is_ok());
+
+    // the newest logs are kept
+    let page = logs
+        .query(LogQuery::new().logtype("counted"))
+        .await
+        .unwrap();
+    counted.sort_by_key(|l| std::cmp::Reverse((l.timestamp, l.id.clone())));
+    let newest: Vec<String> = counted.into_iter().map(|l| l.id).collect();
+    assert_eq!(
+        page.logs.into_iter().map(|l| l.id).collect::<Vec<String>>(),
+        newest[..3]
+    );
+
+    // pruning again removes nothing
+    assert_eq!(logs.prune().await.unwrap().removed, 0);
+}
+
+#[tokio::test]
+async fn export_import_round_trip() {
+    let source = setup(Default::default()).await;
+
+    for i in 0..12 {
+        source
+            .create_log(
+                String::from("exported"),
+                format!("line {i}\nwith \"quotes\""),
+            )
+            .await
+            .unwrap();
+    }
+
+    source
+        .create_log(String::from("other"), String::new())
+        .await
+        .unwrap();
+
+    // pages of 5 logs
+    let mut exported: Vec<u8> = Vec::new();
+    let written = source
+        .export(LogQuery::new().logtype("exported").limit(5), &mut exported)
+        .await
+        .unwrap();
+
+    assert_eq!(written, 12);
+    assert_eq!(exported.iter().filter(|b| **b == b'\n').count(), 12);
+
+    // ids, timestamps and contents are preserved
+    let target = setup(Default::default()).await;
+    let report = target
+        .import(exported.as_slice(), ImportMode::Skip)
+        .await
+        .unwrap();
+    assert_eq!(report.created, 12);
+
+    let all = LogQuery::new().logtype("exported");
+    assert!(
+        target.query(all.clone()).await.unwrap().logs
+            == source.query(all.clone()).await.unwrap().logs
+    );
+
+    // existing logs are skipped or overwritten
+    let report = target
+        .import(exported.as_slice(), ImportMode::Skip)
+        .await
+        .unwrap();
+    assert_eq!((report.created, report.skipped), (0, 12));
+
+    let mut edited = source.query(all.clone()).await.unwrap().logs;
+    edited[0].content = String::from("edited");
+    target.get_log_by_id(edited[0].id.clone()).await.unwrap(); // cached
+
+    let report = target
+        .import(ndjson(&edited[..1]).as_bytes(), ImportMode::Overwrite)
+        .await
+        .unwrap();
+    assert_eq!(report.overwritten, 1);
+    assert_eq!(
+        target
+            .get_log_by_id(edited[0].id.clone())
+            .await
+            .unwrap()
+            .content,
+        "edited"
+    );
+
+    // a malformed line imports nothing
+    let new = Log {
+        id: String::from("new"),
+        logtype: String::from("exported"),
+        timestamp: 1,
+        content: String::new(),
+    };
+    let input = ndjson(&[new]) + "not json\n";
+
+    assert!(target
+        .import(input.as_bytes(), ImportMode::Skip)
+        .await
+        .is_err());
+    assert!(target.get_log_by_id(String::from("new")).await.is_err());
+}
+
+#[tokio::test]
+async fn subscriptions_receive_matching_logs() {
+    let logs = setup(Default::default()).await;
+
+    let filter = LogFilter {
+        logtype: Some(String::from("tailed")),
+        content: Some(String::from("error")),
+    };
+    let stream = logs.subscribe(filter).await.unwrap();
+    let everything = logs.subscribe(LogFilter::default()).await.unwrap();
+
+    logs.create_log(String::from("tailed"), String::from("info: started"))
+        .await
+        .unwrap();
+    logs.create_log(String::from("other"), String::from("error: elsewhere"))
+        .await
+        .unwrap();
+    let matching = logs
+        .create_log(String::from("tailed"), String::from("error: failed"))
+        .await
+        .unwrap();
+    let batch = logs
+        .create_logs(vec![(
+            String::from("tailed"),
+            String::from("error: batched"),
+        )])
+        .await
+        .unwrap();
+
+    let received: Vec<Log> = tokio::time::timeout(Duration::from_secs(5), stream.take(2).collect())
+        .await
+        .unwrap();
+    assert!(received[0] == matching);
+    assert!(received[1] == batch[0]);
+
+    let received: Vec<Log> =
+        tokio::time::timeout(Duration::from_secs(5), everything.take(4).collect())
+            .await
+            .unwrap();
+    assert_eq!(received.len(), 4);
+}
+
+#[tokio::test]
+async fn migrate_converts_text_timestamps() {
+    let db = database().await;
+    let table = unique("Logs");
+
+    // timestamps used to be stored as text, so "10" sorted before "9"
+    dorsal::query(&format!(
+        "CREATE TABLE \"{table}\" (id VARCHAR(255), logtype VARCHAR(255), timestamp VARCHAR(255), content TEXT)"
+    ))
+    .execute(&db.db.client)
+    .await
+    .unwrap();
+
+    for (id, timestamp) in [("nine", "9"), ("ten", "10"), ("big", "1700000000000")] {
+        dorsal::query(&format!(
+            "INSERT INTO \"{table}\" VALUES (?, 'legacy', ?, 'content')"
+        ))
+        .bind(id)
+        .bind(timestamp)
+        .execute(&db.db.client)
+        .await
+        .unwrap();
+    }
+
+    let logs = LogDatabase::new(
+        db,
+        DatabaseOptions {
+            table,
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(logs.migrate().await);
+
+    let page = logs
+        .query(LogQuery::new().order(LogOrder::Ascending).to(100))
+        .await
+        .unwrap();
+    assert_eq!(
+        page.logs
+            .iter()
+            .map(|l| l.id.as_str())
+            .collect::<Vec<&str>>(),
+        vec!["nine", "ten"]
+    );
+
+    assert_eq!(
+        logs.get_log_by_id(String::from("big"))
+            .await
+            .unwrap()
+            .timestamp,
+        1_700_000_000_000
+    );
+
+    // new logs can be created in the migrated table
+    logs.create_log(String::from("legacy"), String::new())
+        .await
+        .unwrap();
+}
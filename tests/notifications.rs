@@ -0,0 +1,926 @@
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::auth_db::DatabaseOptions;
+use dorsal::db::special::log_db::LogKind;
+use dorsal::db::special::notification_db::DatabaseOptions as NotificationOptions;
+use dorsal::db::special::notification_db::{NotificationError, NotificationLog};
+use dorsal::{
+    AuthDatabase, LogDatabase, MemoryMailer, Notification, NotificationDatabase,
+    NotificationPreferences, NotificationTemplate, QuietHours,
+};
+
+use futures_util::StreamExt;
+use std::time::Duration;
+
+/// Create a [`NotificationDatabase`] (and its users table) with unique tables
+async fn setup(options: NotificationOptions) -> (AuthDatabase, NotificationDatabase) {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    logs.init().await;
+
+    let options = NotificationOptions {
+        table: unique("Notifications"),
+        prefix: unique("notification"),
+        ..options
+    };
+
+    let auth = AuthDatabase::new(
+        db.clone(),
+        DatabaseOptions {
+            table: unique("Users"),
+            logs_table: logs.options.table.clone(),
+            notifications_table: Some(options.table.clone()),
+            prefix: unique("user"),
+            notifications_prefix: options.prefix.clone(),
+            ..Default::default()
+        },
+    )
+    .await
+    .with_logs(logs.clone());
+    auth.init().await;
+
+    let notifications = NotificationDatabase::new(db, auth.clone(), logs, options).await;
+    assert!(notifications.init().await);
+
+    (auth, notifications)
+}
+
+/// Create a [`Notification`] for the given user
+fn notification(user: &str, content: &str) -> Notification {
+    Notification {
+        service: String::from("test"),
+        user: user.to_string(),
+        content: content.to_string(),
+        address: String::from("/"),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn subscribe_replays_every_missed_notification() {
+    let (auth, notifications) = setup(Default::default()).await;
+    auth.create_user(String::from("reader")).await.unwrap();
+
+    let mut ids = Vec::new();
+    for i in 0..120 {
+        ids.push(
+            notifications
+                .push_user_notification(notification("reader", &format!("missed {i}")))
+                .await
+                .unwrap()
+                .unwrap()
+                .id,
+        );
+    }
+
+    let stream = notifications
+        .subscribe(String::from("reader"), Some(ids[0].clone()))
+        .await
+        .unwrap();
+
+    let mut replayed: Vec<String> = tokio::time::timeout(
+        Duration::from_secs(5),
+        stream.take(119).map(|n| n.id).collect(),
+    )
+    .await
+    .unwrap();
+
+    // notifications created in the same millisecond are ordered by id
+    replayed.sort();
+    let mut missed = ids[1..].to_vec();
+    missed.sort();
+
+    assert_eq!(replayed, missed);
+}
+
+#[tokio::test]
+async fn subscribe_sends_updates_to_replayed_groups() {
+    let (auth, notifications) = setup(Default::default()).await;
+    auth.create_user(String::from("resumed")).await.unwrap();
+
+    let seen = notifications
+        .push_user_notification(notification("resumed", "seen"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let grouped = || Notification {
+        group: Some(String::from("likes")),
+        ..notification("resumed", "new likes")
+    };
+
+    let group = notifications
+        .push_user_notification(grouped())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut stream = notifications
+        .subscribe(String::from("resumed"), Some(seen.id))
+        .await
+        .unwrap();
+
+    let replayed = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (replayed.id.as_str(), replayed.count),
+        (group.id.as_str(), 1)
+    );
+
+    // merging into the replayed group sends the update
+    notifications
+        .push_user_notification(grouped())
+        .await
+        .unwrap();
+
+    let merged = tokio::time::timeout(Duration::from_secs(5), stream.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((merged.id.as_str(), merged.count), (group.id.as_str(), 2));
+}
+
+#[tokio::test]
+async fn concurrent_grouped_pushes_collapse_into_one() {
+    let (auth, notifications) = setup(Default::default()).await;
+    auth.create_user(String::from("grouped")).await.unwrap();
+
+    let pushes = (0..10).map(|i| {
+        notifications.push_user_notification(Notification {
+            group: Some(String::from("likes")),
+            actors: vec![format!("fan{i}")],
+            ..notification("grouped", "new likes")
+        })
+    });
+
+    for pushed in futures_util::future::join_all(pushes).await {
+        assert!(pushed.unwrap().is_some());
+    }
+
+    let stored = notifications
+        .get_user_notifications(String::from("grouped"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].count, 10);
+    assert_eq!(
+        notifications
+            .unread_count(String::from("grouped"))
+            .await
+            .unwrap(),
+        1
+    );
+
+    // once read, the next push opens a new group
+    notifications
+        .mark_read(String::from("grouped"), stored[0].id.clone())
+        .await
+        .unwrap();
+
+    let reopened = notifications
+        .push_user_notification(Notification {
+            group: Some(String::from("likes")),
+            ..notification("grouped", "new likes")
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_ne!(reopened.id, stored[0].id);
+    assert_eq!(reopened.count, 1);
+}
+
+#[tokio::test]
+async fn digests_are_sent_once() {
+    let (auth, notifications) = setup(Default::default()).await;
+
+    for user in ["digested", "no_email"] {
+        auth.create_user(user.to_string()).await.unwrap();
+        notifications
+            .set_preferences(
+                user.to_string(),
+                NotificationPreferences {
+                    digest: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+    }
+
+    auth.update_user_metadata(String::from("digested"), |m| {
+        m.email = Some(String::from("digested@example.com"))
+    })
+    .await
+    .unwrap();
+
+    for user in ["digested", "no_email"] {
+        for i in 0..3 {
+            notifications
+                .push_user_notification(notification(user, &format!("digest {i}")))
+                .await
+                .unwrap();
+        }
+    }
+
+    // changing preferences while the digest is sent doesn't lose either update
+    let mailer = MemoryMailer::new();
+    let (report, _) = tokio::join!(
+        notifications.send_digests(&mailer),
+        notifications.set_preferences(
+            String::from("digested"),
+            NotificationPreferences {
+                digest: true,
+                muted_services: vec![String::from("muted")],
+                ..Default::default()
+            },
+        )
+    );
+
+    let report = report.unwrap();
+    assert_eq!(report.sent, 1);
+    assert_eq!(report.notifications, 3);
+    assert_eq!(report.failed, 0);
+    assert_eq!(report.unrecorded, 0);
+
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "digested@example.com");
+    assert!(sent[0].text.contains("digest 2"));
+
+    let metadata = auth
+        .get_user_by_username(String::from("digested"))
+        .await
+        .unwrap()
+        .user
+        .metadata;
+
+    assert!(metadata.last_digest.is_some());
+    assert_eq!(metadata.notifications.muted_services, vec!["muted"]);
+
+    // the same notifications aren't sent again
+    mailer.clear();
+    let report = notifications.send_digests(&mailer).await.unwrap();
+
+    assert_eq!(report.sent, 0);
+    assert!(mailer.sent().is_empty());
+}
+
+#[tokio::test]
+async fn migrated_notifications_keep_read_state() {
+    let (auth, notifications) = setup(Default::default()).await;
+    auth.create_user(String::from("migrated")).await.unwrap();
+    auth.create_user(String::from("stranger")).await.unwrap();
+
+    // notifications used to be stored as logs, these all share a timestamp
+    let legacy = notifications
+        .logs
+        .create_logs(
+            (0..60)
+                .map(|i| {
+                    (
+                        NotificationLog::LOGTYPE.to_string(),
+                        serde_json::to_string(&notification("migrated", &format!("legacy {i}")))
+                            .unwrap(),
+                    )
+                })
+                .collect(),
+        )
+        .await
+        .unwrap();
+
+    assert!(notifications.migrate().await);
+
+    // pages don't repeat or skip notifications with the same timestamp
+    let mut ids: Vec<String> = Vec::new();
+    for offset in [0, 50] {
+        ids.extend(
+            notifications
+                .get_user_notifications(String::from("migrated"), Some(offset))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|n| n.id),
+        );
+    }
+
+    let mut expected: Vec<String> = legacy.iter().map(|l| l.id.clone()).collect();
+    expected.sort();
+    expected.reverse();
+    assert_eq!(ids, expected);
+
+    // read state
+    assert_eq!(
+        notifications
+            .unread_count(String::from("migrated"))
+            .await
+            .unwrap(),
+        60
+    );
+
+    notifications
+        .mark_read(String::from("migrated"), ids[0].clone())
+        .await
+        .unwrap();
+    // marking it again changes nothing
+    notifications
+        .mark_read(String::from("migrated"), ids[0].clone())
+        .await
+        .unwrap();
+
+    let read = notifications
+        .get_notification(String::from("migrated"), ids[0].clone())
+        .await
+        .unwrap();
+    assert!(read.read_at.is_some());
+    assert_eq!(read.count, 1);
+
+    // other users can't read or delete the notification
+    assert!(notifications
+        .mark_read(String::from("stranger"), ids[1].clone())
+        .await
+        .is_err());
+    assert!(notifications
+        .delete_notification(String::from("stranger"), ids[1].clone())
+        .await
+        .is_err());
+
+    assert_eq!(
+        notifications
+            .mark_all_read(String::from("migrated"))
+            .await
+            .unwrap(),
+        59
+    );
+    assert_eq!(
+        notifications
+            .unread_count(String::from("migrated"))
+            .await
+            .unwrap(),
+        0
+    );
+
+    notifications
+        .delete_notification(String::from("migrated"), ids[1].clone())
+        .await
+        .unwrap();
+    assert!(notifications
+        .get_notification(String::from("migrated"), ids[1].clone())
+        .await
+        .is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn unread_counts_stay_correct_under_concurrency() {
+    let (auth, notifications) = setup(Default::default()).await;
+    auth.create_user(String::from("counted")).await.unwrap();
+
+    // counts read while notifications are created or deleted must not be cached as the
+    // current count
+    let mut deleted: i64 = 0;
+    for round in 1..=20 {
+        let previous = notifications
+            .get_user_notifications(String::from("counted"), Some(0))
+            .await
+            .unwrap();
+
+        let mut tasks = Vec::new();
+        for i in 0..5 {
+            let notifications = notifications.clone();
+            tasks.push(tokio::spawn(async move {
+                notifications
+                    .push_user_notification(notification("counted", &format!("{round} {i}")))
+                    .await
+                    .unwrap();
+            }));
+        }
+
+        if let Some(n) = previous.first() {
+            let notifications = notifications.clone();
+            let id = n.id.clone();
+            deleted += 1;
+            tasks.push(tokio::spawn(async move {
+                notifications
+                    .delete_notification(String::from("counted"), id)
+                    .await
+                    .unwrap();
+            }));
+        }
+
+        for _ in 0..5 {
+            let notifications = notifications.clone();
+            tasks.push(tokio::spawn(async move {
+                notifications
+                    .unread_count(String::from("counted"))
+                    .await
+                    .unwrap();
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(
+            notifications
+                .unread_count(String::from("counted"))
+                .await
+                .unwrap(),
+            round * 5 - deleted
+        );
+    }
+
+    // reading a page of them concurrently (twice each) never goes below zero
+    let ids: Vec<String> = notifications
+        .get_user_notifications(String::from("counted"), Some(0))
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|n| n.id)
+        .collect();
+    assert_eq!(ids.len(), 50);
+
+    let reads = ids.iter().chain(ids.iter()).map(|id| {
+        let notifications = notifications.clone();
+        let id = id.clone();
+        tokio::spawn(async move {
+            notifications
+                .mark_read(String::from("counted"), id)
+                .await
+                .unwrap();
+            notifications
+                .unread_count(String::from("counted"))
+                .await
+                .unwrap()
+        })
+    });
+
+    for task in reads.collect::<Vec<_>>() {
+        assert!(task.await.unwrap() >= 0);
+    }
+
+    assert_eq!(
+        notifications
+            .unread_count(String::from("counted"))
+            .await
+            .unwrap(),
+        50 - deleted
+    );
+}
+
+#[tokio::test]
+async fn preferences_mute_and_defer_notifications() {
+    let (auth, notifications) = setup(Default::default()).await;
+    auth.create_user(String::from("picky")).await.unwrap();
+
+    // invalid preferences and missing users
+    assert!(matches!(
+        notifications
+            .set_preferences(
+                String::from("picky"),
+                NotificationPreferences {
+                    quiet_hours: Some(QuietHours { start: 22, end: 24 }),
+                    ..Default::default()
+                },
+            )
+            .await,
+        Err(NotificationError::ValueError)
+    ));
+    assert!(matches!(
+        notifications
+            .set_preferences(String::from("nobody"), Default::default())
+            .await,
+        Err(NotificationError::NotFound)
+    ));
+
+    let preferences = NotificationPreferences {
+        muted_services: vec![String::from("spam")],
+        digest: true,
+        ..Default::default()
+    };
+    notifications
+        .set_preferences(String::from("picky"), preferences.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        notifications
+            .get_preferences(String::from("picky"))
+            .await
+            .unwrap(),
+        preferences
+    );
+
+    let mut stream = notifications
+        .subscribe(String::from("picky"), None)
+        .await
+        .unwrap();
+
+    // muted services aren't stored
+    assert!(notifications
+        .push_user_notification(Notification {
+            service: String::from("spam"),
+            ..notification("picky", "buy now")
+        })
+        .await
+        .unwrap()
+        .is_none());
+
+    // digest mode stores notifications without delivering them
+    let deferred = notifications
+        .push_user_notification(notification("picky", "deferred"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), stream.next())
+            .await
+            .is_err()
+    );
+
+    let stored = notifications
+        .get_user_notifications(String::from("picky"), None)
+        .await
+        .unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].id, deferred.id);
+
+    // back to real time
+    notifications
+        .set_preferences(String::from("picky"), Default::default())
+        .await
+        .unwrap();
+
+    let delivered = notifications
+        .push_user_notification(notification("picky", "delivered"))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .id,
+        delivered.id
+    );
+
+    // quiet hours can span midnight
+    let hour = 60 * 60 * 1000;
+    let overnight = QuietHours { start: 22, end: 6 };
+    assert!(overnight.contains(23 * hour));
+    assert!(overnight.contains(24 * hour + 5 * hour));
+    assert!(!overnight.contains(6 * hour));
+    assert!(!overnight.contains(12 * hour));
+
+    let daytime = NotificationPreferences {
+        quiet_hours: Some(QuietHours { start: 9, end: 17 }),
+        ..Default::default()
+    };
+    assert!(!daytime.is_realtime(12 * hour));
+    assert!(daytime.is_realtime(20 * hour));
+}
+
+#[tokio::test]
+async fn bulk_and_broadcast_pushes_skip_invalid_recipients() {
+    let (auth, notifications) = setup(NotificationOptions {
+        batch_size: 2,
+        ..Default::default()
+    })
+    .await;
+
+    for user in ["bulk1", "bulk2", "bulk3", "muter", "banned"] {
+        auth.create_user(user.to_string()).await.unwrap();
+    }
+
+    notifications
+        .set_preferences(
+            String::from("muter"),
+            NotificationPreferences {
+                muted_services: vec![String::from("test")],
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    dorsal::query(&format!(
+        "UPDATE \"{}\" SET \"role\" = 'banned' WHERE \"username\" = 'banned'",
+        auth.options.table
+    ))
+    .execute(&auth.base.db.client)
+    .await
+    .unwrap();
+
+    // missing, banned and muting users are skipped
+    let mut pushed = notifications
+        .push_bulk(
+            ["bulk1", "bulk2", "bulk3", "muter", "banned", "missing"]
+                .iter()
+                .map(|u| u.to_string())
+                .collect(),
+            notification("ignored", "announcement"),
+        )
+        .await
+        .unwrap();
+    pushed.sort_by(|a, b| a.user.cmp(&b.user));
+
+    assert_eq!(
+        pushed.iter().map(|n| n.user.as_str()).collect::<Vec<_>>(),
+        vec!["bulk1", "bulk2", "bulk3"]
+    );
+    assert!(pushed.iter().all(|n| n.timestamp == pushed[0].timestamp));
+
+    for user in ["bulk1", "bulk2", "bulk3"] {
+        let stored = notifications
+            .get_user_notifications(user.to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].content, "announcement");
+    }
+
+    // invalid templates insert nothing
+    assert!(matches!(
+        notifications
+            .push_bulk(vec![String::from("bulk1")], notification("ignored", " "))
+            .await,
+        Err(NotificationError::ValueError)
+    ));
+
+    // broadcasts
+    dorsal::query(&format!(
+        "UPDATE \"{}\" SET \"role\" = 'admin' WHERE \"username\" = 'bulk1'",
+        auth.options.table
+    ))
+    .execute(&auth.base.db.client)
+    .await
+    .unwrap();
+
+    assert_eq!(
+        notifications
+            .broadcast(
+                Some(String::from("admin")),
+                notification("ignored", "admins")
+            )
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        notifications
+            .broadcast(
+                Some(String::from("banned")),
+                notification("ignored", "none")
+            )
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        notifications
+            .broadcast(None, notification("ignored", "everyone"))
+            .await
+            .unwrap(),
+        3
+    );
+
+    assert_eq!(
+        notifications
+            .unread_count(String::from("bulk1"))
+            .await
+            .unwrap(),
+        3
+    );
+    assert_eq!(
+        notifications
+            .unread_count(String::from("banned"))
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn templates_render_in_the_recipients_locale() {
+    let reply = NotificationTemplate::new("forum", "reply")
+        .with_locale("en", "{actors} replied to {thread}")
+        .with_locale("pt", "{actors} respondeu em {thread}");
+
+    let (auth, notifications) = setup(NotificationOptions {
+        templates: vec![reply.clone()],
+        ..Default::default()
+    })
+    .await;
+
+    auth.create_user(String::from("english")).await.unwrap();
+    auth.create_user(String::from("brazilian")).await.unwrap();
+    auth.update_user_metadata(String::from("brazilian"), |m| {
+        m.locale = Some(String::from("pt-BR"))
+    })
+    .await
+    .unwrap();
+
+    let replied = |user: &str| Notification {
+        service: String::from("forum"),
+        event: Some(String::from("reply")),
+        params: [(String::from("thread"), String::from("Rust"))].into(),
+        actors: vec![String::from("ana")],
+        ..notification(user, "placeholder")
+    };
+
+    // regional locales fall back to their language
+    let pushed = notifications
+        .push_user_notification(replied("brazilian"))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(pushed.content, "ana respondeu em Rust");
+
+    notifications
+        .push_user_notification(replied("english"))
+        .await
+        .unwrap();
+
+    // the default locale is stored
+    let (stored,) = notifications
+        .base
+        .fetch_one_as::<(String,)>(
+            dorsal::query(&format!(
+                "SELECT \"content\" FROM \"{}\" WHERE \"id\" = ?",
+                notifications.options.table
+            ))
+            .bind(&pushed.id),
+        )
+        .await
+        .unwrap();
+    assert_eq!(stored, "ana replied to Rust");
+
+    // changed wording is used for stored notifications
+    let reworded = NotificationDatabase::new(
+        notifications.base.clone(),
+        auth.clone(),
+        notifications.logs.clone(),
+        NotificationOptions {
+            templates: vec![NotificationTemplate::new("forum", "reply")
+                .with_locale("en", "{actors} answered in {thread}")
+                .with_locale("pt", "{actors} respondeu no tópico {thread}")],
+            ..notifications.options.clone()
+        },
+    )
+    .await;
+
+    assert_eq!(
+        reworded
+            .get_user_notifications(String::from("brazilian"), None)
+            .await
+            .unwrap()[0]
+            .content,
+        "ana respondeu no tópico Rust"
+    );
+    assert_eq!(
+        reworded
+            .get_user_notifications(String::from("english"), None)
+            .await
+            .unwrap()[0]
+            .content,
+        "ana answered in Rust"
+    );
+
+    // missing parameters and locales
+    let params = [(String::from("actors"), String::from("bo"))].into();
+    assert_eq!(
+        reply.render("fr", "en", &params).unwrap(),
+        "bo replied to {thread}"
+    );
+    assert_eq!(reply.render("fr", "de", &params), None);
+}
+
+#[tokio::test]
+async fn pushes_validate_notifications() {
+    let (auth, notifications) = setup(NotificationOptions {
+        max_content_length: 64,
+        ..Default::default()
+    })
+    .await;
+    auth.create_user(String::from("validated")).await.unwrap();
+
+    assert!(matches!(
+        notifications
+            .push_user_notification(notification("missing", "hello"))
+            .await,
+        Err(NotificationError::NotFound)
+    ));
+
+    let with = |content: &str, address: &str| Notification {
+        address: address.to_string(),
+        ..notification("validated", content)
+    };
+
+    for invalid in [
+        with("", "/"),
+        with("   ", "/"),
+        with(&"a".repeat(65), "/"),
+        with("hello", "javascript:alert(1)"),
+        with("hello", "JavaScript:alert(1)"),
+        with("hello", "data:text/html,hi"),
+        with("hello", "//evil.example"),
+        with("hello", "/\\evil.example"),
+        with("hello", "https://"),
+        with("hello", "/path with spaces"),
+        with("hello", &format!("/{}", "a".repeat(64))),
+        Notification {
+            service: String::new(),
+            ..with("hello", "/")
+        },
+    ] {
+        assert!(
+            matches!(
+                notifications.push_user_notification(invalid.clone()).await,
+                Err(NotificationError::ValueError)
+            ),
+            "{:?} was accepted",
+            (invalid.content, invalid.address)
+        );
+    }
+
+    for valid in [
+        with("hello", ""),
+        with("hello", "/posts/1?tab=replies"),
+        with("hello", "https://example.com/posts/1"),
+        with("hello", "HTTP://example.com"),
+        with(&"a".repeat(64), "/"),
+    ] {
+        assert!(notifications
+            .push_user_notification(valid)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    assert_eq!(
+        notifications
+            .unread_count(String::from("validated"))
+            .await
+            .unwrap(),
+        5
+    );
+}
+
+#[tokio::test]
+async fn digests_are_rendered_in_each_recipients_locale() {
+    let (auth, notifications) = setup(NotificationOptions {
+        templates: vec![NotificationTemplate::new("forum", "reply")
+            .with_locale("en", "{actors} replied")
+            .with_locale("pt", "{actors} respondeu")],
+        ..Default::default()
+    })
+    .await;
+
+    for (user, locale) in [("reader_en", None), ("reader_pt", Some("pt"))] {
+        auth.create_user(user.to_string()).await.unwrap();
+        auth.update_user_metadata(user.to_string(), |m| {
+            m.email = Some(format!("{user}@example.com"));
+            m.locale = locale.map(String::from);
+            m.notifications.digest = true;
+        })
+        .await
+        .unwrap();
+
+        notifications
+            .push_user_notification(Notification {
+                service: String::from("forum"),
+                event: Some(String::from("reply")),
+                actors: vec![String::from("ana")],
+                ..notification(user, "placeholder")
+            })
+            .await
+            .unwrap();
+    }
+
+    let mailer = MemoryMailer::new();
+    let report = notifications.send_digests(&mailer).await.unwrap();
+    assert_eq!(report.sent, 2);
+
+    let mut sent = mailer.sent();
+    sent.sort_by(|a, b| a.to.cmp(&b.to));
+
+    assert_eq!(sent[0].to, "reader_en@example.com");
+    assert!(sent[0].text.contains("ana replied"));
+    assert_eq!(sent[1].to, "reader_pt@example.com");
+    assert!(sent[1].text.contains("ana respondeu"));
+}
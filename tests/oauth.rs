@@ -0,0 +1,167 @@
+//! OAuth2 login against a local mock provider
+#![cfg(feature = "oauth")]
+mod common;
+
+use common::{database, unique, Stub};
+use dorsal::db::special::oauth_db::{DatabaseOptions, OAuthError};
+use dorsal::{AuthDatabase, OAuthDatabase, OAuthProvider};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Get the query parameters of a URL (values are not decoded)
+fn params(url: &str) -> HashMap<String, String> {
+    url.split_once('?')
+        .unwrap()
+        .1
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Get the form fields of a request body (values are not decoded)
+fn form(body: &str) -> HashMap<String, String> {
+    body.split('&')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[tokio::test]
+async fn login_with_mock_provider() {
+    let db = database().await;
+
+    let provider = Stub::start(|request| match request.path.as_str() {
+        "/token" if request.method == "POST" => {
+            if form(&request.body).get("code").map(|c| c.as_str()) == Some("good-code") {
+                (200, String::from("{\"access_token\":\"mock-token\"}"))
+            } else {
+                (400, String::from("{\"error\":\"invalid_grant\"}"))
+            }
+        }
+        "/userinfo" => {
+            if request.headers.get("authorization").map(|a| a.as_str()) == Some("Bearer mock-token")
+            {
+                (200, String::from("{\"sub\":\"external-1\"}"))
+            } else {
+                (401, String::new())
+            }
+        }
+        _ => (404, String::new()),
+    })
+    .await;
+
+    let auth = AuthDatabase::new(
+        db.clone(),
+        dorsal::db::special::auth_db::DatabaseOptions {
+            table: unique("Users"),
+            prefix: unique("user"),
+            ..Default::default()
+        },
+    )
+    .await;
+    auth.init().await;
+
+    let oauth = OAuthDatabase::new(
+        db.clone(),
+        auth.clone(),
+        DatabaseOptions {
+            table: unique("Identities"),
+            prefix: unique("oauth"),
+            providers: vec![OAuthProvider {
+                name: String::from("mock"),
+                client_id: String::from("client"),
+                client_secret: None,
+                authorize_url: format!("{}/authorize", provider.url),
+                token_url: format!("{}/token", provider.url),
+                userinfo_url: format!("{}/userinfo", provider.url),
+                redirect_url: String::from("http://localhost/callback"),
+                scopes: vec![String::from("profile")],
+                subject_field: String::from("sub"),
+            }],
+        },
+    )
+    .await;
+    assert!(oauth.init().await);
+
+    // unknown provider
+    assert!(matches!(
+        oauth.begin_login(String::from("other")).await,
+        Err(OAuthError::NotFound)
+    ));
+
+    // login with an identity that isn't linked yet
+    let url = oauth.begin_login(String::from("mock")).await.unwrap();
+    assert!(url.starts_with(&format!("{}/authorize?", provider.url)));
+
+    let query = params(&url);
+    assert_eq!(query["code_challenge_method"], "S256");
+
+    let login = oauth
+        .finish_login(String::from("good-code"), query["state"].clone())
+        .await
+        .unwrap();
+
+    assert_eq!(login.identity.subject, "external-1");
+    assert!(login.identity.username.is_none());
+    assert!(login.user.is_none());
+
+    // the verifier sent to the provider matches the challenge
+    let token_request = provider
+        .requests()
+        .into_iter()
+        .find(|r| r.path == "/token")
+        .unwrap();
+    let verifier = form(&token_request.body)["code_verifier"].clone();
+
+    assert_eq!(
+        URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())),
+        query["code_challenge"]
+    );
+
+    // states can only be used once
+    assert!(matches!(
+        oauth
+            .finish_login(String::from("good-code"), query["state"].clone())
+            .await,
+        Err(OAuthError::InvalidState)
+    ));
+
+    assert!(matches!(
+        oauth
+            .finish_login(String::from("good-code"), String::from("unknown"))
+            .await,
+        Err(OAuthError::InvalidState)
+    ));
+
+    // login with a linked identity
+    let username = unique("oauth_user");
+    auth.create_user(username.clone()).await.unwrap();
+    oauth
+        .link_identity(
+            String::from("mock"),
+            String::from("external-1"),
+            username.clone(),
+        )
+        .await
+        .unwrap();
+
+    let url = oauth.begin_login(String::from("mock")).await.unwrap();
+    let login = oauth
+        .finish_login(String::from("good-code"), params(&url)["state"].clone())
+        .await
+        .unwrap();
+
+    assert_eq!(login.user.unwrap().user.username, username);
+
+    // provider errors
+    let url = oauth.begin_login(String::from("mock")).await.unwrap();
+    assert!(matches!(
+        oauth
+            .finish_login(String::from("bad-code"), params(&url)["state"].clone())
+            .await,
+        Err(OAuthError::ProviderError)
+    ));
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::auth_db::{AuditAction, AuditContext, DatabaseOptions};
+use dorsal::{AuthDatabase, LogDatabase};
+
+#[tokio::test]
+async fn erase_only_rewrites_usernames() {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    logs.init().await;
+
+    // usernames are the same as values of other fields, or contain wildcards
+    let auth = AuthDatabase::new(
+        db.clone(),
+        DatabaseOptions {
+            table: unique("Users"),
+            logs_table: logs.options.table.clone(),
+            notifications_table: None,
+            prefix: unique("user"),
+            ..Default::default()
+        },
+    )
+    .await
+    .with_logs(logs.clone());
+    auth.init().await;
+
+    for username in ["ban", "member", "alice", "a_b", "aXb"] {
+        auth.create_user(String::from(username)).await.unwrap();
+    }
+
+    let ctx = |actor: &str| AuditContext {
+        actor: String::from(actor),
+        ip: Some(String::from("127.0.0.1")),
+    };
+
+    auth.ban_user(ctx("member"), String::from("alice"))
+        .await
+        .unwrap();
+    auth.ban_user(ctx("ban"), String::from("aXb"))
+        .await
+        .unwrap();
+
+    // the export of "a_b" doesn't include entries of "aXb"
+    let export = auth.export_user_data(String::from("a_b")).await.unwrap();
+    assert!(export.audit.is_empty());
+
+    let export = auth.export_user_data(String::from("ban")).await.unwrap();
+    assert_eq!(export.audit.len(), 1);
+
+    // erase
+    auth.erase_user(String::from("ban")).await.unwrap();
+    auth.erase_user(String::from("member")).await.unwrap();
+
+    let entries = auth
+        .get_user_audit_log(String::from("alice"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].actor, "[deleted]");
+    assert_eq!(entries[0].action, AuditAction::Ban);
+    assert_eq!(entries[0].before.as_deref(), Some("member"));
+    assert_eq!(entries[0].ip.as_deref(), Some("127.0.0.1"));
+
+    let entries = auth
+        .get_user_audit_log(String::from("aXb"), None)
+        .await
+        .unwrap();
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].actor, "[deleted]");
+    assert_eq!(entries[0].target, "aXb");
+
+    assert!(auth
+        .get_user_by_username(String::from("ban"))
+        .await
+        .is_err());
+    assert!(auth.get_user_by_username(String::from("a_b")).await.is_ok());
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::auth_db::{DatabaseOptions, UserMetadata, UserState};
+use dorsal::AuthDatabase;
+
+#[derive(Debug, PartialEq, sqlx::FromRow)]
+struct Paste {
+    url: String,
+    views: i64,
+    #[sqlx(try_from = "i64")]
+    timestamp: u128,
+    edited: Option<String>,
+}
+
+#[tokio::test]
+async fn rows_decode_into_structs() {
+    let db = database().await;
+    let table = unique("Pastes");
+
+    dorsal::query(&format!(
+        "CREATE TABLE \"{table}\" (url VARCHAR(255), views BIGINT, timestamp BIGINT, edited TEXT)"
+    ))
+    .execute(&db.db.client)
+    .await
+    .unwrap();
+
+    for (url, views, edited) in [("a", 1, None), ("b", 2, Some("yes"))] {
+        dorsal::query(&format!("INSERT INTO \"{table}\" VALUES (?, ?, ?, ?)"))
+            .bind(url)
+            .bind(views as i64)
+            .bind(1_700_000_000_000_i64)
+            .bind(edited)
+            .execute(&db.db.client)
+            .await
+            .unwrap();
+    }
+
+    let paste = db
+        .fetch_one_as::<Paste>(
+            dorsal::query(&format!("SELECT * FROM \"{table}\" WHERE \"url\" = ?")).bind("b"),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        paste,
+        Paste {
+            url: String::from("b"),
+            views: 2,
+            timestamp: 1_700_000_000_000,
+            edited: Some(String::from("yes")),
+        }
+    );
+
+    let pastes = db
+        .fetch_all_as::<Paste>(dorsal::query(&format!(
+            "SELECT * FROM \"{table}\" ORDER BY \"url\" ASC"
+        )))
+        .await
+        .unwrap();
+    assert_eq!(pastes.len(), 2);
+    assert_eq!(pastes[0].edited, None);
+
+    // tuples decode single columns
+    let (count,) = db
+        .fetch_one_as::<(i64,)>(dorsal::query(&format!("SELECT COUNT(*) FROM \"{table}\"")))
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+
+    // missing rows are errors
+    assert!(matches!(
+        db.fetch_one_as::<Paste>(
+            dorsal::query(&format!("SELECT * FROM \"{table}\" WHERE \"url\" = ?")).bind("c"),
+        )
+        .await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn malformed_metadata_is_a_decode_error() {
+    let db = database().await;
+    let auth = AuthDatabase::new(
+        db.clone(),
+        DatabaseOptions {
+            table: unique("Users"),
+            prefix: unique("user"),
+            notifications_table: None,
+            ..Default::default()
+        },
+    )
+    .await;
+    auth.init().await;
+
+    auth.create_user(String::from("decoded")).await.unwrap();
+
+    dorsal::query(&format!(
+        "INSERT INTO \"{}\" VALUES ('broken', 'hash', 'member', 0, 'not json')",
+        auth.options.table
+    ))
+    .execute(&db.db.client)
+    .await
+    .unwrap();
+
+    let select = format!(
+        "SELECT * FROM \"{}\" WHERE \"username\" = ?",
+        auth.options.table
+    );
+
+    let user = db
+        .fetch_one_as::<UserState<UserMetadata>>(dorsal::query(&select).bind("decoded"))
+        .await
+        .unwrap();
+    assert_eq!(user.role, "member");
+    assert!(user.timestamp > 0);
+
+    assert!(matches!(
+        db.fetch_one_as::<UserState<UserMetadata>>(dorsal::query(&select).bind("broken"))
+            .await,
+        Err(sqlx::Error::ColumnDecode { .. })
+    ));
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use common::{database, unique};
+use dorsal::db::special::log_db::{ImportMode, Log};
+use dorsal::LogDatabase;
+
+#[tokio::test]
+async fn search_survives_vacuum() {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(logs.init().await);
+
+    let mut created = Vec::new();
+    for i in 0..20 {
+        created.push(
+            logs.create_log(String::from("search"), format!("entry number{i} apple"))
+                .await
+                .unwrap(),
+        );
+    }
+
+    // logs created before the index are indexed, calling it again doesn't duplicate them
+    logs.create_search_index().await.unwrap();
+    logs.create_search_index().await.unwrap();
+
+    let banana = logs
+        .create_log(String::from("search"), String::from("banana split"))
+        .await
+        .unwrap();
+
+    // remove the first logs, vacuum may renumber the rowids of the others
+    for log in &created[..10] {
+        logs.delete_log(log.id.clone()).await.unwrap();
+    }
+
+    logs.edit_log(created[15].id.clone(), String::from("edited apple pie"))
+        .await
+        .unwrap();
+
+    dorsal::query("VACUUM")
+        .execute(&db.db.client)
+        .await
+        .unwrap();
+
+    let found = logs.search(String::from("banana"), None, 10).await.unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, banana.id);
+
+    let found = logs
+        .search(String::from("apple number12"), None, 10)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, created[12].id);
+
+    assert_eq!(
+        logs.search(String::from("apple"), None, 50)
+            .await
+            .unwrap()
+            .len(),
+        10
+    );
+    assert_eq!(
+        logs.search(String::from("pie"), Some(String::from("search")), 10)
+            .await
+            .unwrap()[0]
+            .id,
+        created[15].id
+    );
+    assert!(logs
+        .search(String::from("number3"), None, 10)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn search_index_keeps_ids_unique() {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(logs.init().await);
+
+    let log = logs
+        .create_log(String::from("search"), String::from("original words"))
+        .await
+        .unwrap();
+
+    logs.create_search_index().await.unwrap();
+
+    // overwriting an imported log reindexes it
+    let edited = Log {
+        content: String::from("replaced words"),
+        ..log.clone()
+    };
+    let report = logs
+        .import(
+            (serde_json::to_string(&edited).unwrap() + "\n").as_bytes(),
+            ImportMode::Overwrite,
+        )
+        .await
+        .unwrap();
+    assert_eq!(report.overwritten, 1);
+
+    assert!(logs
+        .search(String::from("original"), None, 10)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        logs.search(String::from("replaced"), None, 10)
+            .await
+            .unwrap()[0]
+            .id,
+        log.id
+    );
+
+    // a duplicate id is rejected by the logs table, other logs are still indexed
+    let duplicate = format!("INSERT INTO \"{}\" VALUES (?, ?, ?, ?)", logs.options.table);
+    assert!(dorsal::query(&duplicate)
+        .bind(&log.id)
+        .bind("search")
+        .bind(0_i64)
+        .bind("duplicate")
+        .execute(&db.db.client)
+        .await
+        .is_err());
+
+    let other = logs
+        .create_log(String::from("search"), String::from("other words"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        logs.search(String::from("words"), None, 10)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        logs.search(String::from("other"), None, 10).await.unwrap()[0].id,
+        other.id
+    );
+}
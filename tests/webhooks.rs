@@ -0,0 +1,288 @@
+#![cfg(feature = "webhooks")]
+mod common;
+
+use common::{database, unique, Stub};
+use dorsal::db::special::notification_db::DatabaseOptions as NotificationOptions;
+use dorsal::db::special::webhook_db::{
+    service_owner, sign_payload, verify_payload, DatabaseOptions, WebhookPayload,
+};
+use dorsal::{AuthDatabase, LogDatabase, Notification, NotificationDatabase, WebhookDatabase};
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Create a [`WebhookDatabase`] with unique tables
+async fn setup(options: DatabaseOptions) -> WebhookDatabase {
+    let db = database().await;
+
+    let logs = LogDatabase::new(
+        db.clone(),
+        dorsal::db::special::log_db::DatabaseOptions {
+            table: unique("Logs"),
+            prefix: unique("log"),
+            ..Default::default()
+        },
+    )
+    .await;
+    logs.init().await;
+
+    let webhooks = WebhookDatabase::new(db, logs, options).await;
+    assert!(webhooks.init().await);
+    webhooks
+}
+
+#[tokio::test]
+async fn rejects_internal_addresses() {
+    let webhooks = setup(DatabaseOptions {
+        table: unique("Webhooks"),
+        queue_table: unique("WebhookQueue"),
+        ..Default::default()
+    })
+    .await;
+
+    for url in [
+        "http://127.0.0.1:8080/hook",
+        "http://localhost/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://10.0.0.1/hook",
+        "http://192.168.1.1/hook",
+        "http://[::1]/hook",
+        "http://[::ffff:127.0.0.1]/hook",
+        "ftp://example.com/hook",
+        "not a url",
+    ] {
+        assert!(
+            webhooks
+                .create_webhook(String::from("owner"), url.to_string(), Vec::new())
+                .await
+                .is_err(),
+            "{url} was accepted"
+        );
+    }
+}
+
+#[tokio::test]
+async fn delivers_signed_payloads_and_retries() {
+    // the first attempt fails
+    let calls = AtomicU32::new(0);
+    let stub = Stub::start(move |_| {
+        if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+            (500, String::new())
+        } else {
+            (200, String::new())
+        }
+    })
+    .await;
+
+    let options = DatabaseOptions {
+        table: unique("Webhooks"),
+        queue_table: unique("WebhookQueue"),
+        backoff: Duration::from_millis(50),
+        allow_private_addresses: true,
+        ..Default::default()
+    };
+
+    let webhooks = setup(options.clone()).await;
+    let webhook = webhooks
+        .create_webhook(
+            String::from("owner"),
+            format!("{}/hook", stub.url),
+            vec![String::from("test.event")],
+        )
+        .await
+        .unwrap();
+
+    // events the webhook doesn't accept aren't queued
+    assert!(webhooks
+        .dispatch(vec![String::from("owner")], "other.event", &"ignored")
+        .await
+        .unwrap()
+        .is_empty());
+
+    let ids = webhooks
+        .dispatch(vec![String::from("owner")], "test.event", &"data")
+        .await
+        .unwrap();
+    assert_eq!(ids.len(), 1);
+
+    // retries are made from the queue (by another instance, as if the server restarted)
+    let restarted =
+        WebhookDatabase::new(webhooks.base.clone(), webhooks.logs.clone(), options).await;
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while stub.requests().len() < 2 {
+            let _ = restarted.deliver_pending().await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    // the queue is empty
+    assert_eq!(restarted.deliver_pending().await.unwrap(), 0);
+    assert_eq!(stub.requests().len(), 2);
+
+    // every attempt is signed, and has the same delivery id
+    for request in stub.requests() {
+        assert_eq!(request.path, "/hook");
+        assert_eq!(request.headers["x-webhook-id"], ids[0]);
+        assert_eq!(request.headers["x-webhook-event"], "test.event");
+
+        let timestamp: u64 = request.headers["x-webhook-timestamp"].parse().unwrap();
+        let signature = &request.headers["x-webhook-signature"];
+
+        assert!(verify_payload(
+            &webhook.secret,
+            timestamp,
+            &request.body,
+            signature,
+            300
+        ));
+        assert!(!verify_payload(
+            &webhook.secret,
+            timestamp,
+            &request.body.replace("data", "atad"),
+            signature,
+            300
+        ));
+    }
+
+    // replayed deliveries are rejected
+    let old = dorsal::token::now() - 600;
+    assert!(!verify_payload(
+        &webhook.secret,
+        old,
+        "{}",
+        &sign_payload(&webhook.secret, old, "{}"),
+        300
+    ));
+
+    // every attempt is recorded
+    let mut deliveries = webhooks
+        .get_webhook_deliveries(webhook.id.clone(), None)
+        .await
+        .unwrap()
+        .logs
+        .into_iter()
+        .map(|l| l.payload)
+        .collect::<Vec<_>>();
+    deliveries.sort_by_key(|d| d.attempt);
+
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!(deliveries[0].status, Some(500));
+    assert!(!deliveries[0].success);
+    assert_eq!(deliveries[1].attempt, 2);
+    assert!(deliveries[1].success);
+
+    // ids are matched exactly, not as part of the logged json
+    assert!(webhooks
+        .get_webhook_deliveries(format!("{}\",\"event", webhook.id), None)
+        .await
+        .unwrap()
+        .logs
+        .is_empty());
+}
+
+#[tokio::test]
+async fn services_and_users_own_separate_webhooks() {
+    let stub = Stub::start(|_| (200, String::new())).await;
+
+    let webhooks = setup(DatabaseOptions {
+        table: unique("Webhooks"),
+        queue_table: unique("WebhookQueue"),
+        allow_private_addresses: true,
+        ..Default::default()
+    })
+    .await;
+
+    let auth = AuthDatabase::new(
+        webhooks.base.clone(),
+        dorsal::db::special::auth_db::DatabaseOptions {
+            table: unique("Users"),
+            logs_table: webhooks.logs.options.table.clone(),
+            prefix: unique("user"),
+            ..Default::default()
+        },
+    )
+    .await
+    .with_logs(webhooks.logs.clone());
+    auth.init().await;
+
+    let notifications = NotificationDatabase::new(
+        webhooks.base.clone(),
+        auth.clone(),
+        webhooks.logs.clone(),
+        NotificationOptions {
+            table: unique("Notifications"),
+            prefix: unique("notification"),
+            ..Default::default()
+        },
+    )
+    .await
+    .with_webhooks(webhooks.clone());
+    assert!(notifications.init().await);
+
+    // a user with the same name as a service
+    auth.create_user(String::from("alerts")).await.unwrap();
+    auth.create_user(String::from("bob")).await.unwrap();
+
+    let user = webhooks
+        .create_webhook(
+            String::from("alerts"),
+            format!("{}/user", stub.url),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    let service = webhooks
+        .create_webhook(
+            service_owner("alerts"),
+            format!("{}/service", stub.url),
+            Vec::new(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(service.owner, "service:alerts");
+
+    // sent by the service to bob, then by another service to the user
+    for (user, service) in [("bob", "alerts"), ("alerts", "other")] {
+        notifications
+            .push_user_notification(Notification {
+                service: service.to_string(),
+                user: user.to_string(),
+                content: String::from("hello"),
+                address: String::from("/"),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while stub.requests().len() < 2 {
+            let _ = webhooks.deliver_pending().await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    let mut received: Vec<(String, String)> = stub
+        .requests()
+        .into_iter()
+        .map(|r| {
+            let payload: WebhookPayload<Notification> = serde_json::from_str(&r.body).unwrap();
+            (r.path, payload.data.user)
+        })
+        .collect();
+    received.sort();
+
+    assert_eq!(
+        received,
+        vec![
+            (String::from("/service"), String::from("bob")),
+            (String::from("/user"), String::from("alerts")),
+        ]
+    );
+    assert_ne!(user.id, service.id);
+}